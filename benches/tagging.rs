@@ -0,0 +1,74 @@
+//! Throughput of recursive tagging on a generated tree.
+//!
+//! `cargo bench --bench tagging`, set `STAG_BENCH_FILES` for a bigger tree.
+
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use stag::{collect_paths, walk_paths, TagStore};
+use tempfile::TempDir;
+
+const FILES_PER_DIR: usize = 1000;
+
+fn generate_tree(root: &Path, files: usize) -> std::io::Result<()> {
+    for i in 0..files {
+        let dir = root.join(format!("dir{}", i / FILES_PER_DIR));
+        if i % FILES_PER_DIR == 0 {
+            fs::create_dir(&dir)?;
+        }
+        fs::write(dir.join(format!("file{}", i)), "")?;
+    }
+    Ok(())
+}
+
+fn report(name: &str, paths: usize, elapsed: Duration) {
+    println!(
+        "{:<10} {:>8} paths in {:>8.2?} ({:>9.0} paths/s)",
+        name,
+        paths,
+        elapsed,
+        paths as f64 / elapsed.as_secs_f64()
+    );
+}
+
+fn main() -> stag::Result<()> {
+    let files = std::env::var("STAG_BENCH_FILES")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(50_000);
+
+    let tree = TempDir::new()?;
+    generate_tree(tree.path(), files)?;
+    let root = vec![tree.path().to_path_buf()];
+
+    let db_dir = TempDir::new()?;
+
+    // Everything walked up front, one big transaction
+    let mut store = TagStore::open(db_dir.path().join("batch.db"))?;
+    let start = Instant::now();
+    let paths = collect_paths(root.clone(), true, false, 1)?;
+    store.add_tags_batch(&paths, "bench")?;
+    report("batch", paths.len(), start.elapsed());
+
+    // Walker straight into chunked transactions
+    let mut store = TagStore::open(db_dir.path().join("stream.db"))?;
+    let start = Instant::now();
+    let tagged = store.add_tags_stream(walk_paths(root.clone(), true, false, 1), "bench")?;
+    report("stream", tagged.total(), start.elapsed());
+
+    // Same, with one walker thread per core feeding the writer
+    let mut store = TagStore::open(db_dir.path().join("parallel.db"))?;
+    let start = Instant::now();
+    let tagged = store.add_tags_stream(walk_paths(root.clone(), true, false, 0), "bench")?;
+    report("parallel", tagged.total(), start.elapsed());
+
+    // Everything already tagged, the common case for re-running a tagging job
+    let start = Instant::now();
+    let tagged = store.add_tags_stream(walk_paths(root, true, false, 0), "bench")?;
+    report("retag", tagged.total(), start.elapsed());
+
+    Ok(())
+}
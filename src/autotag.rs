@@ -1,55 +1,117 @@
-use anyhow::Result;
 use std::{
     collections::{HashMap, HashSet},
     fs,
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
-use crate::{cmd::collect_paths, tagstore::TagStore};
-use mime_guess::MimeGuess;
+use crate::{
+    git::git_tags,
+    image::image_tags,
+    project::{dependency_tags, project_tags},
+    sniff::file_type,
+    walk_map, Changes, Progress, Result, RuleSet, TagChange, TagStore,
+};
+
+/// Tag name to the paths that would get it.
+pub type TagPlan = HashMap<String, Vec<PathBuf>>;
+
+/// Works out which tags each path would get, without touching the store.
+/// Paths that can't be read are skipped. Tags are generated on the walker
+/// threads, see [`walk_map`] for what `threads` and `progress` mean.
+pub fn plan_autotags(
+    paths: Vec<PathBuf>,
+    recursive: bool,
+    hidden: bool,
+    threads: usize,
+    rules: &RuleSet,
+    progress: &Progress,
+) -> Result<TagPlan> {
+    let mut tag_map: TagPlan = HashMap::new();
+
+    let rules = rules.clone();
+    let tagged = walk_map(paths, recursive, hidden, threads, progress, move |path| {
+        generate_tags_with(&path, &rules)
+            .ok()
+            .map(|tags| (path, tags))
+    });
+
+    for (path, tags) in progress.track(tagged) {
+        for tag in tags {
+            tag_map.entry(tag).or_default().push(path.clone())
+        }
+    }
 
-// TODO: Consider how this error handles
+    Ok(tag_map)
+}
 
+/// Tags paths with everything [`generate_tags_with`] comes up with.
+/// The changes count tag/path pairs, not paths.
 pub fn autotag_paths(
     store: &mut TagStore,
     paths: Vec<PathBuf>,
-    preview: bool,
     recursive: bool,
     hidden: bool,
-) -> Result<()> {
-    let paths = collect_paths(paths, recursive, hidden)?;
+    threads: usize,
+    rules: &RuleSet,
+    progress: &Progress,
+) -> Result<Changes> {
+    let mut changes = Changes::default();
+
+    for (tag, paths) in plan_autotags(paths, recursive, hidden, threads, rules, progress)? {
+        changes += store.add_tags_batch(&paths, &tag)?;
+    }
 
-    let mut tag_map: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    Ok(changes)
+}
 
-    for path in paths {
-        if let Ok(metadata) = fs::metadata(&path) {
-            let tags = generate_tags_from_metadata(&metadata, &path)?;
-            for tag in tags {
-                tag_map.entry(tag).or_default().push(path.clone())
-            }
-        }
+/// The tags a plan would actually add, ie. without the ones paths already have.
+/// Sorted by path, then tag.
+pub fn diff_autotags(store: &TagStore, plan: &TagPlan) -> Result<Vec<TagChange>> {
+    let mut changes = Vec::new();
+
+    for (tag, paths) in plan {
+        changes.extend(store.diff_tags(paths.iter().cloned(), tag, true)?);
     }
 
-    if preview {
-        print!("[PREVIEW, NO CHANGES] got tags: ");
-        let keys_str = tag_map
-            .keys()
-            .map(|k| k.as_str())
-            .collect::<Vec<_>>()
-            .join(", ");
-        println!("{}", keys_str);
+    changes.sort();
+    Ok(changes)
+}
 
-        return Ok(());
-    }
+/// The metadata based tags for a single path (file type, size, MIME, ...).
+pub fn generate_tags(path: &Path) -> Result<Vec<String>> {
+    builtin_tags(path, &RuleSet::default())
+}
 
-    for (tag, paths) in tag_map {
-        store.add_tags_batch(&paths, &tag)?;
+/// Just the built-in tags, with the options `rules` has for them (ie. `namespace_deps`).
+pub fn builtin_tags(path: &Path, rules: &RuleSet) -> Result<Vec<String>> {
+    let metadata = fs::metadata(path)?;
+    let mut tags = generate_tags_from_metadata(&metadata, path, rules)?;
+    tags.sort();
+    Ok(tags)
+}
+
+/// Tags for a single path from `rules`, with or without the built-in ones.
+pub fn generate_tags_with(path: &Path, rules: &RuleSet) -> Result<Vec<String>> {
+    let metadata = fs::metadata(path)?;
+
+    let mut tags = match rules.builtin() {
+        true => generate_tags_from_metadata(&metadata, path, rules)?,
+        false => Vec::new(),
+    };
+    for rule in rules.matching(&metadata, path) {
+        tags.extend(rule.tags.iter().cloned());
     }
 
-    Ok(())
+    tags.sort();
+    tags.dedup();
+    Ok(tags)
 }
 
-fn generate_tags_from_metadata(metadata: &fs::Metadata, path: &PathBuf) -> Result<Vec<String>> {
+fn generate_tags_from_metadata(
+    metadata: &fs::Metadata,
+    path: &Path,
+    rules: &RuleSet,
+) -> Result<Vec<String>> {
     let mut tags: HashSet<String> = HashSet::new();
 
     if metadata.is_dir() {
@@ -58,6 +120,10 @@ fn generate_tags_from_metadata(metadata: &fs::Metadata, path: &PathBuf) -> Resul
         if path.join(".git").is_dir() {
             tags.insert("git".to_string());
         }
+        tags.extend(git_tags(path, rules.git_stale_after()));
+
+        tags.extend(project_tags(path));
+        tags.extend(dependency_tags(path, rules.namespace_deps()));
     } else if metadata.is_file() {
         tags.insert("file".to_string());
 
@@ -70,17 +136,36 @@ fn generate_tags_from_metadata(metadata: &fs::Metadata, path: &PathBuf) -> Resul
             tags.insert("large".to_string());
         }
 
-        let guess: MimeGuess = mime_guess::from_path(&path);
-        for mime in guess {
-            // Tag with primary type (for example, "image", "text", "application")
-            tags.insert(mime.type_().as_str().to_string());
+        // Sniffed from the content when possible, the extension can lie
+        let file_type = file_type(path);
+        if file_type
+            .mimes
+            .iter()
+            .any(|mime| mime.starts_with("image/"))
+        {
+            tags.extend(image_tags(path));
+        }
+        for mime in file_type.mimes {
+            if let Some((type_, subtype)) = mime.split_once('/') {
+                // Tag with primary type (for example, "image", "text", "application")
+                tags.insert(type_.to_string());
 
-            // Tag with subtype (for example, "png", "html", "json",)
-            tags.insert(mime.subtype().as_str().to_string());
+                // Tag with subtype (for example, "png", "html", "json",)
+                tags.insert(subtype.to_string());
+            }
 
             // Tag with the full MIME string (e.g., "mime:text/html").
             tags.insert(format!("mime:{}", mime));
         }
+
+        if let Some(interpreter) = file_type.interpreter {
+            tags.insert("script".to_string());
+            tags.insert(format!("shebang:{}", interpreter));
+        }
+
+        if file_type.mismatched {
+            tags.insert("mismatched-extension".to_string());
+        }
     }
 
     Ok(tags.into_iter().collect())
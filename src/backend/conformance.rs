@@ -0,0 +1,252 @@
+//! Behaviour every [`TagBackend`] has to share. Each check runs once per backend,
+//! see the `conformance_tests!` invocations at the bottom.
+
+use std::collections::BTreeSet;
+
+use super::{MemoryBackend, Relocation, SqliteBackend, TagBackend};
+use crate::{Query, Result};
+
+fn paths(paths: &[&str]) -> Vec<String> {
+    paths.iter().map(|p| p.to_string()).collect()
+}
+
+// Backends don't promise any ordering
+fn set(paths: Vec<String>) -> BTreeSet<String> {
+    paths.into_iter().collect()
+}
+
+fn add_and_list(backend: &mut dyn TagBackend) -> Result<()> {
+    backend.add_tags(&paths(&["/a", "/b"]), "tag")?;
+
+    assert_eq!(set(backend.list_tagged("tag")?), set(paths(&["/a", "/b"])));
+    assert!(backend.list_tagged("other")?.is_empty());
+    Ok(())
+}
+
+fn add_is_idempotent(backend: &mut dyn TagBackend) -> Result<()> {
+    assert_eq!(backend.add_tags(&paths(&["/a"]), "tag")?, 1);
+    assert_eq!(backend.add_tags(&paths(&["/a", "/b"]), "tag")?, 1);
+    backend.remove_tags(&paths(&["/b"]), "tag")?;
+
+    assert_eq!(backend.list_tagged("tag")?, paths(&["/a"]));
+    assert_eq!(backend.get_tags("/a")?, vec!["tag"]);
+    Ok(())
+}
+
+fn get_tags(backend: &mut dyn TagBackend) -> Result<()> {
+    backend.add_tags(&paths(&["/a"]), "one")?;
+    backend.add_tags(&paths(&["/a"]), "two")?;
+
+    assert_eq!(set(backend.get_tags("/a")?), set(paths(&["one", "two"])));
+    assert!(backend.get_tags("/unknown")?.is_empty());
+    Ok(())
+}
+
+fn remove(backend: &mut dyn TagBackend) -> Result<()> {
+    backend.add_tags(&paths(&["/a", "/b"]), "tag")?;
+    backend.add_tags(&paths(&["/a"]), "keep")?;
+    assert_eq!(backend.remove_tags(&paths(&["/a", "/c"]), "tag")?, 1);
+
+    assert_eq!(backend.list_tagged("tag")?, paths(&["/b"]));
+    assert_eq!(backend.get_tags("/a")?, vec!["keep"]);
+
+    // Unknown paths and tags are not an error
+    backend.remove_tags(&paths(&["/unknown"]), "tag")?;
+    backend.remove_tags(&paths(&["/a"]), "unknown")?;
+    Ok(())
+}
+
+fn untag(backend: &mut dyn TagBackend) -> Result<()> {
+    backend.add_tags(&paths(&["/a", "/a/b", "/a/b/c", "/ab"]), "x")?;
+    backend.add_tags(&paths(&["/a/b"]), "y")?;
+
+    assert_eq!(backend.untag(&paths(&["/a"]), false, false)?, 1);
+    assert!(backend.get_tags("/a")?.is_empty());
+    assert_eq!(backend.get_tags("/a/b")?.len(), 2);
+
+    // Only what's below /a, not /ab next to it
+    assert_eq!(backend.untag(&paths(&["/a/"]), true, true)?, 3);
+    assert_eq!(backend.list_tagged("x")?, paths(&["/ab"]));
+    assert!(backend.list_tagged("y")?.is_empty());
+
+    assert_eq!(backend.untag(&paths(&["/unknown"]), true, false)?, 0);
+    Ok(())
+}
+
+fn relocate(backend: &mut dyn TagBackend) -> Result<()> {
+    backend.add_tags(&paths(&["/old", "/old/a", "/old/b", "/older"]), "x")?;
+    backend.add_tags(&paths(&["/new/b"]), "y")?;
+
+    let relocation = backend.relocate("/old", "/new")?;
+    assert_eq!(
+        relocation,
+        Relocation {
+            moved: 3,
+            merged: 1
+        }
+    );
+
+    assert_eq!(
+        set(backend.list_tagged("x")?),
+        set(paths(&["/new", "/new/a", "/new/b", "/older"]))
+    );
+    // Merged, not replaced
+    assert_eq!(set(backend.get_tags("/new/b")?), set(paths(&["x", "y"])));
+    assert!(backend.get_tags("/old/b")?.is_empty());
+
+    assert_eq!(backend.relocate("/missing", "/new")?, Relocation::default());
+    Ok(())
+}
+
+fn relocate_up(backend: &mut dyn TagBackend) -> Result<()> {
+    backend.add_tags(&paths(&["/a"]), "w")?;
+    backend.add_tags(&paths(&["/a/b"]), "x")?;
+    backend.add_tags(&paths(&["/a/b/x"]), "y")?;
+    backend.add_tags(&paths(&["/a/b/b/x"]), "z")?;
+
+    let relocation = backend.relocate("/a/b", "/a")?;
+    assert_eq!(
+        relocation,
+        Relocation {
+            moved: 3,
+            merged: 1
+        }
+    );
+
+    // Every path moved exactly one level up, none landed on one still to move
+    assert_eq!(set(backend.get_tags("/a")?), set(paths(&["w", "x"])));
+    assert_eq!(backend.get_tags("/a/x")?, vec!["y"]);
+    assert_eq!(backend.get_tags("/a/b/x")?, vec!["z"]);
+    assert!(backend.get_tags("/a/b/b/x")?.is_empty());
+    Ok(())
+}
+
+fn tags_under(backend: &mut dyn TagBackend) -> Result<()> {
+    backend.add_tags(&paths(&["/a/b", "/a", "/ab"]), "y")?;
+    backend.add_tags(&paths(&["/a/b"]), "x")?;
+
+    let pair = |path: &str, tag: &str| (path.to_string(), tag.to_string());
+    assert_eq!(
+        backend.tags_under("/a")?,
+        vec![pair("/a", "y"), pair("/a/b", "x"), pair("/a/b", "y")]
+    );
+    assert!(backend.tags_under("/missing")?.is_empty());
+    Ok(())
+}
+
+fn search(backend: &mut dyn TagBackend) -> Result<()> {
+    // /a: [x, y], /b: [x, z], /c: [z]
+    backend.add_tags(&paths(&["/a", "/b"]), "x")?;
+    backend.add_tags(&paths(&["/a"]), "y")?;
+    backend.add_tags(&paths(&["/b", "/c"]), "z")?;
+
+    let all = Query::new().tags(["x", "y"]);
+    assert_eq!(set(backend.search(&all)?), set(paths(&["/a"])));
+
+    let any = Query::new().tags(["y", "z"]).any(true);
+    assert_eq!(set(backend.search(&any)?), set(paths(&["/a", "/b", "/c"])));
+
+    let excluded = Query::new().tag("x").exclude("z");
+    assert_eq!(set(backend.search(&excluded)?), set(paths(&["/a"])));
+
+    let any_excluded = Query::new().tags(["x", "z"]).any(true).exclude("y");
+    assert_eq!(
+        set(backend.search(&any_excluded)?),
+        set(paths(&["/b", "/c"]))
+    );
+
+    assert!(backend.search(&Query::new())?.is_empty());
+    assert!(backend.search(&Query::new().tag("missing"))?.is_empty());
+    Ok(())
+}
+
+fn search_under(backend: &mut dyn TagBackend) -> Result<()> {
+    backend.add_tags(&paths(&["/a", "/a/b", "/a/b/c", "/ab", "/a%"]), "x")?;
+
+    let under = |dir: &str| Query::new().tag("x").under(dir);
+    assert_eq!(
+        set(backend.search(&under("/a"))?),
+        set(paths(&["/a", "/a/b", "/a/b/c"]))
+    );
+    assert_eq!(
+        set(backend.search(&under("/a/b/"))?),
+        set(paths(&["/a/b", "/a/b/c"]))
+    );
+    assert_eq!(backend.search(&under("/"))?.len(), 5);
+    assert!(backend.search(&under("/missing"))?.is_empty());
+    Ok(())
+}
+
+fn special_characters(backend: &mut dyn TagBackend) -> Result<()> {
+    for tag in ["태그", "🏷️", "tag with spaces", "@#$%", "50%_off"] {
+        backend.add_tags(&paths(&["/ü/ñ i"]), tag)?;
+        assert_eq!(backend.list_tagged(tag)?, paths(&["/ü/ñ i"]));
+    }
+    Ok(())
+}
+
+macro_rules! conformance_tests {
+    ($name:ident, $backend:expr) => {
+        mod $name {
+            use super::*;
+
+            #[test]
+            fn add_and_list() -> Result<()> {
+                super::add_and_list(&mut $backend)
+            }
+
+            #[test]
+            fn add_is_idempotent() -> Result<()> {
+                super::add_is_idempotent(&mut $backend)
+            }
+
+            #[test]
+            fn get_tags() -> Result<()> {
+                super::get_tags(&mut $backend)
+            }
+
+            #[test]
+            fn remove() -> Result<()> {
+                super::remove(&mut $backend)
+            }
+
+            #[test]
+            fn untag() -> Result<()> {
+                super::untag(&mut $backend)
+            }
+
+            #[test]
+            fn relocate() -> Result<()> {
+                super::relocate(&mut $backend)
+            }
+
+            #[test]
+            fn relocate_up() -> Result<()> {
+                super::relocate_up(&mut $backend)
+            }
+
+            #[test]
+            fn tags_under() -> Result<()> {
+                super::tags_under(&mut $backend)
+            }
+
+            #[test]
+            fn search() -> Result<()> {
+                super::search(&mut $backend)
+            }
+
+            #[test]
+            fn search_under() -> Result<()> {
+                super::search_under(&mut $backend)
+            }
+
+            #[test]
+            fn special_characters() -> Result<()> {
+                super::special_characters(&mut $backend)
+            }
+        }
+    };
+}
+
+conformance_tests!(sqlite, SqliteBackend::open_in_memory()?);
+conformance_tests!(memory, MemoryBackend::new());
@@ -0,0 +1,139 @@
+use std::collections::{btree_map::Entry, BTreeMap, BTreeSet};
+
+use super::{descendant_range, is_under, relocated, Relocation, TagBackend};
+use crate::{Query, Result};
+
+/// Keeps everything in a map, nothing is persisted. Fast for tests and for
+/// embedding stag where a database file would be overkill.
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    files: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TagBackend for MemoryBackend {
+    fn add_tags(&mut self, paths: &[String], tag: &str) -> Result<usize> {
+        Ok(paths
+            .iter()
+            .filter(|path| {
+                self.files
+                    .entry(path.to_string())
+                    .or_default()
+                    .insert(tag.to_string())
+            })
+            .count())
+    }
+
+    fn remove_tags(&mut self, paths: &[String], tag: &str) -> Result<usize> {
+        Ok(paths
+            .iter()
+            .filter(|path| {
+                self.files
+                    .get_mut(path.as_str())
+                    .is_some_and(|tags| tags.remove(tag))
+            })
+            .count())
+    }
+
+    fn untag(&mut self, paths: &[String], recursive: bool, forget: bool) -> Result<usize> {
+        let mut removed = 0;
+
+        for path in paths {
+            let mut matched = vec![path.clone()];
+            if recursive {
+                let (start, end) = descendant_range(path);
+                matched.extend(self.files.range(start..end).map(|(p, _)| p.clone()));
+            }
+
+            for path in matched {
+                if forget {
+                    removed += self.files.remove(&path).map_or(0, |tags| tags.len());
+                } else if let Some(tags) = self.files.get_mut(&path) {
+                    removed += tags.len();
+                    tags.clear();
+                }
+            }
+        }
+
+        Ok(removed)
+    }
+
+    fn relocate(&mut self, from: &str, to: &str) -> Result<Relocation> {
+        let mut moving: Vec<String> = self
+            .files
+            .keys()
+            .filter(|path| is_under(path, from))
+            .cloned()
+            .collect();
+        // Same order as the SQLite backend, see select_files_under.sql
+        moving.sort_by_key(|path| path.len());
+
+        let mut relocation = Relocation::default();
+        for path in moving {
+            let tags = self.files.remove(&path).unwrap_or_default();
+
+            match self.files.entry(relocated(&path, from, to)) {
+                Entry::Occupied(mut existing) => {
+                    existing.get_mut().extend(tags);
+                    relocation.merged += 1;
+                }
+                Entry::Vacant(target) => {
+                    target.insert(tags);
+                }
+            }
+            relocation.moved += 1;
+        }
+
+        Ok(relocation)
+    }
+
+    fn get_tags(&self, path: &str) -> Result<Vec<String>> {
+        Ok(self
+            .files
+            .get(path)
+            .map(|tags| tags.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    fn tags_under(&self, dir: &str) -> Result<Vec<(String, String)>> {
+        Ok(self
+            .files
+            .iter()
+            .filter(|(path, _)| is_under(path, dir))
+            .flat_map(|(path, tags)| tags.iter().map(|tag| (path.clone(), tag.clone())))
+            .collect())
+    }
+
+    fn list_tagged(&self, tag: &str) -> Result<Vec<String>> {
+        Ok(self
+            .files
+            .iter()
+            .filter(|(_, tags)| tags.contains(tag))
+            .map(|(path, _)| path.clone())
+            .collect())
+    }
+
+    fn search(&self, query: &Query) -> Result<Vec<String>> {
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        Ok(self
+            .files
+            .iter()
+            .filter(|(path, tags)| {
+                query.matches(tags)
+                    && query
+                        .under
+                        .as_ref()
+                        .is_none_or(|dir| is_under(path, &dir.to_string_lossy()))
+            })
+            .map(|(path, _)| path.clone())
+            .collect())
+    }
+}
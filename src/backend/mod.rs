@@ -0,0 +1,68 @@
+//! Storage for tags. [`TagStore`](crate::TagStore) resolves filesystem paths and
+//! hands the backend plain path strings, so a backend never touches the disk
+//! for anything but its own storage.
+
+mod memory;
+mod sqlite;
+
+#[cfg(test)]
+mod conformance;
+
+pub use memory::MemoryBackend;
+pub use sqlite::{SqliteBackend, DEFAULT_BUSY_TIMEOUT};
+
+use crate::{Query, Result};
+
+/// What [`TagBackend::relocate`] did, `merged` paths are included in `moved`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Relocation {
+    pub moved: usize,
+    pub merged: usize,
+}
+
+pub trait TagBackend {
+    /// Tags every path with `tag`, all or nothing.
+    /// Returns how many paths didn't have the tag yet.
+    fn add_tags(&mut self, paths: &[String], tag: &str) -> Result<usize>;
+
+    /// Untags every path, paths or tags that aren't stored are ignored.
+    /// Returns how many paths actually had the tag.
+    fn remove_tags(&mut self, paths: &[String], tag: &str) -> Result<usize>;
+
+    /// Drops every tag from the paths, with `recursive` also from everything stored
+    /// below them. `forget` removes the paths from the store entirely.
+    /// Returns how many tag/path pairs were removed.
+    fn untag(&mut self, paths: &[String], recursive: bool, forget: bool) -> Result<usize>;
+
+    /// Moves every path at or below `from` to the same place below `to`, all or nothing.
+    /// A path that's already stored at the destination gets the moved tags merged in.
+    fn relocate(&mut self, from: &str, to: &str) -> Result<Relocation>;
+
+    fn get_tags(&self, path: &str) -> Result<Vec<String>>;
+
+    /// Every path/tag pair at or below `dir`, sorted by path then tag.
+    fn tags_under(&self, dir: &str) -> Result<Vec<(String, String)>>;
+
+    fn list_tagged(&self, tag: &str) -> Result<Vec<String>>;
+
+    fn search(&self, query: &Query) -> Result<Vec<String>>;
+}
+
+/// Bounds of the stored paths strictly below `dir`, as a half open range.
+/// `'0'` is the character right after `'/'`, so `/a/b` is in it but `/ab` isn't.
+pub(crate) fn descendant_range(dir: &str) -> (String, String) {
+    let dir = dir.trim_end_matches('/');
+    (format!("{}/", dir), format!("{}0", dir))
+}
+
+/// Whether the stored `path` is `dir` or somewhere below it, same as [`descendant_range`].
+pub(crate) fn is_under(path: &str, dir: &str) -> bool {
+    let (start, end) = descendant_range(dir);
+    path == dir.trim_end_matches('/') || (path >= start.as_str() && path < end.as_str())
+}
+
+/// `path` (at or below `from`) moved below `to` instead.
+pub(crate) fn relocated(path: &str, from: &str, to: &str) -> String {
+    let rest = &path[from.trim_end_matches('/').len()..];
+    format!("{}{}", to.trim_end_matches('/'), rest)
+}
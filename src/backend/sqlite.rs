@@ -0,0 +1,356 @@
+use std::{path::Path, thread, time::Duration};
+
+use rusqlite::{
+    params, params_from_iter, Connection, ErrorCode, OptionalExtension, ToSql, Transaction,
+    TransactionBehavior,
+};
+
+use super::{descendant_range, relocated, Relocation, TagBackend};
+use crate::{Error, Query, Result};
+
+/// How long a connection waits on another process holding the write lock.
+pub const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Rows per multi-row INSERT, well below SQLite's bound parameter limit
+const BULK_ROWS: usize = 500;
+
+// Extra attempts on top of the busy timeout, for the cases SQLite refuses to
+// wait on (ie. switching journal mode while another connection opens the db)
+const BUSY_RETRIES: u32 = 5;
+
+/// The default backend, a single SQLite database file.
+///
+/// The database runs in WAL mode so readers never block writers, and every
+/// write takes the write lock upfront, waiting up to the busy timeout for it.
+/// That's what lets shell prompts, editors and cron jobs all use stag at once.
+pub struct SqliteBackend {
+    conn: Connection,
+}
+
+fn is_busy(err: &rusqlite::Error) -> bool {
+    matches!(
+        err.sqlite_error_code(),
+        Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+    )
+}
+
+/// Runs `op` again with a growing backoff while the database is busy.
+fn retry_busy<T>(mut op: impl FnMut() -> Result<T, rusqlite::Error>) -> Result<T, rusqlite::Error> {
+    let mut attempt = 0;
+
+    loop {
+        match op() {
+            Err(err) if is_busy(&err) && attempt < BUSY_RETRIES => {
+                attempt += 1;
+                thread::sleep(Duration::from_millis(50 << attempt));
+            }
+            result => return result,
+        }
+    }
+}
+
+// SQL inject me, whatever
+// It's a local bundled database, why validate 5Head
+mod schemas {
+    pub const INIT_SQL: &str = include_str!("../sql/schema/init.sql");
+}
+
+mod queries {
+    pub const REMOVE_TAGS: &str = include_str!("../sql/queries/remove_tags.sql");
+    pub const LIST_TAGS: &str = include_str!("../sql/queries/list_tags.sql");
+    pub const GET_FILE_TAGS: &str = include_str!("../sql/queries/get_file_tags.sql");
+    pub const UNTAG_FILES: &str = include_str!("../sql/queries/untag_files.sql");
+    pub const FORGET_FILES: &str = include_str!("../sql/queries/forget_files.sql");
+    pub const SELECT_FILES_UNDER: &str = include_str!("../sql/queries/select_files_under.sql");
+    pub const GET_FILE_ID: &str = include_str!("../sql/queries/get_file_id.sql");
+    pub const MOVE_FILE: &str = include_str!("../sql/queries/move_file.sql");
+    pub const MERGE_FILE_TAGS: &str = include_str!("../sql/queries/merge_file_tags.sql");
+    pub const DELETE_FILE_TAGS: &str = include_str!("../sql/queries/delete_file_tags.sql");
+    pub const DELETE_FILE: &str = include_str!("../sql/queries/delete_file.sql");
+    pub const TAGS_UNDER: &str = include_str!("../sql/queries/tags_under.sql");
+}
+
+mod templates {
+    pub const BULK_INSERT_FILES: &str = include_str!("../sql/templates/bulk_insert_files.sql");
+    pub const BULK_INSERT_FILE_TAGS: &str =
+        include_str!("../sql/templates/bulk_insert_file_tags.sql");
+    pub const EXCLUDE_CLAUSE: &str = include_str!("../sql/templates/exclude_clause.sql");
+    pub const UNDER_CLAUSE: &str = include_str!("../sql/templates/under_clause.sql");
+    pub const SEARCH_QUERY: &str = include_str!("../sql/templates/search_query.sql");
+}
+
+impl SqliteBackend {
+    fn init_db(conn: &Connection) -> Result<()> {
+        retry_busy(|| conn.execute_batch(schemas::INIT_SQL)).map_err(Error::Schema)?;
+
+        Ok(())
+    }
+
+    /// Opens (and creates if needed) the database at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_timeout(path, DEFAULT_BUSY_TIMEOUT)
+    }
+
+    /// Like [`SqliteBackend::open`], waiting up to `busy_timeout` for other writers.
+    pub fn open_with_timeout(path: impl AsRef<Path>, busy_timeout: Duration) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(busy_timeout)?;
+
+        retry_busy(|| {
+            conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))
+        })?;
+        // Durable enough with WAL, and a lot less fsync
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+
+        Self::init_db(&conn)?;
+        Ok(Self { conn })
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        let conn = Connection::open_in_memory()?;
+        Self::init_db(&conn)?;
+        Ok(Self { conn })
+    }
+
+    // NOTE: Helper / Internal functions
+    fn get_or_create_tag(tx: &Transaction, tag: &str) -> Result<i64> {
+        let mut stmt = tx.prepare_cached("INSERT OR IGNORE INTO tags (name) VALUES (?1)")?;
+        stmt.execute([tag])?;
+
+        let mut stmt = tx.prepare_cached("SELECT id FROM tags WHERE name = ?1")?;
+        Ok(stmt.query_row([tag], |row| row.get(0))?)
+    }
+
+    /// Inserts the files and their tag, up to `BULK_ROWS` paths per statement.
+    /// Full groups all render the same SQL, so the statement cache keeps hitting.
+    fn bulk_tag_files(tx: &Transaction, paths: &[String], tag_id: i64) -> Result<usize> {
+        let mut added = 0;
+
+        for group in paths.chunks(BULK_ROWS) {
+            let values = vec!["(?)"; group.len()].join(",");
+            let mut stmt =
+                tx.prepare_cached(&templates::BULK_INSERT_FILES.replace("{values}", &values))?;
+            stmt.execute(params_from_iter(group))?;
+
+            let path_placeholders = (2..group.len() + 2)
+                .map(|i| format!("?{}", i))
+                .collect::<Vec<_>>()
+                .join(",");
+            let mut stmt = tx.prepare_cached(
+                &templates::BULK_INSERT_FILE_TAGS
+                    .replace("{path_placeholders}", &path_placeholders),
+            )?;
+
+            let mut params: Vec<&dyn ToSql> = vec![&tag_id];
+            params.extend(group.iter().map(|p| p as &dyn ToSql));
+            added += stmt.execute(params_from_iter(params))?;
+        }
+
+        Ok(added)
+    }
+
+    /// Runs `write` in an immediate transaction, retrying the whole thing while busy.
+    /// Taking the write lock upfront means a writer never has to upgrade a read
+    /// snapshot, which SQLite can only fail (not wait) on.
+    fn write<T, F>(&mut self, mut write: F) -> Result<T>
+    where
+        F: FnMut(&Transaction) -> Result<T>,
+    {
+        let mut attempt = 0;
+
+        loop {
+            let result = self
+                .conn
+                .transaction_with_behavior(TransactionBehavior::Immediate)
+                .map_err(Error::from)
+                .and_then(|tx| {
+                    let value = write(&tx)?;
+                    tx.commit()?;
+                    Ok(value)
+                });
+
+            match result {
+                Err(Error::Database(err)) if is_busy(&err) && attempt < BUSY_RETRIES => {
+                    attempt += 1;
+                    thread::sleep(Duration::from_millis(50 << attempt));
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Renders the query into SQL and the parameters to bind, in order.
+    fn search_sql(query: &Query) -> (String, Vec<String>) {
+        let include_placeholders = vec!["?"; query.include.len()].join(",");
+        let exclude_placeholders = if !query.exclude.is_empty() {
+            vec!["?"; query.exclude.len()].join(",")
+        } else {
+            String::new()
+        };
+
+        let having_clause = if query.any {
+            "HAVING COUNT(DISTINCT t.name) >= 1".into()
+        } else {
+            format!("HAVING COUNT(DISTINCT t.name) = {}", query.include.len())
+        };
+
+        let exclude_clause = if !query.exclude.is_empty() {
+            templates::EXCLUDE_CLAUSE.replace("{exclude_placeholders}", &exclude_placeholders)
+        } else {
+            String::new()
+        };
+
+        let under_clause = if query.under.is_some() {
+            templates::UNDER_CLAUSE
+        } else {
+            ""
+        };
+
+        let sql = templates::SEARCH_QUERY
+            .replace("{include_placeholders}", &include_placeholders)
+            .replace("{exclude_clause}", &exclude_clause)
+            .replace("{under_clause}", under_clause)
+            .replace("{having_clause}", &having_clause);
+
+        let mut params: Vec<String> = query.include.clone();
+        params.extend(query.exclude.iter().cloned());
+
+        // A range on the unique path index instead of LIKE, no escaping `%` and `_`
+        if let Some(dir) = &query.under {
+            let dir = dir.to_string_lossy();
+            let (start, end) = descendant_range(&dir);
+            params.extend([dir.trim_end_matches('/').to_string(), start, end]);
+        }
+
+        (sql, params)
+    }
+}
+
+impl TagBackend for SqliteBackend {
+    fn add_tags(&mut self, paths: &[String], tag: &str) -> Result<usize> {
+        self.write(|tx| {
+            let tag_id = Self::get_or_create_tag(tx, tag)?;
+            Self::bulk_tag_files(tx, paths, tag_id)
+        })
+    }
+
+    fn remove_tags(&mut self, paths: &[String], tag: &str) -> Result<usize> {
+        self.write(|tx| {
+            let mut stmt = tx.prepare_cached(queries::REMOVE_TAGS)?;
+
+            let mut removed = 0;
+            for path in paths {
+                removed += stmt.execute(params![path, tag])?;
+            }
+
+            Ok(removed)
+        })
+    }
+
+    fn untag(&mut self, paths: &[String], recursive: bool, forget: bool) -> Result<usize> {
+        self.write(|tx| {
+            let mut untag = tx.prepare_cached(queries::UNTAG_FILES)?;
+            let mut forget_files = tx.prepare_cached(queries::FORGET_FILES)?;
+
+            let mut removed = 0;
+            for path in paths {
+                let (start, end) = descendant_range(path);
+                let params = params![path, recursive, start, end];
+
+                removed += untag.execute(params)?;
+                if forget {
+                    forget_files.execute(params)?;
+                }
+            }
+
+            Ok(removed)
+        })
+    }
+
+    fn relocate(&mut self, from: &str, to: &str) -> Result<Relocation> {
+        self.write(|tx| {
+            let (start, end) = descendant_range(from);
+            let moving = tx
+                .prepare_cached(queries::SELECT_FILES_UNDER)?
+                .query_map(params![from.trim_end_matches('/'), start, end], |row| {
+                    Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let mut relocation = Relocation::default();
+            for (id, path) in moving {
+                let target = relocated(&path, from, to);
+                let existing: Option<i64> = tx
+                    .prepare_cached(queries::GET_FILE_ID)?
+                    .query_row([&target], |row| row.get(0))
+                    .optional()?;
+
+                match existing {
+                    Some(existing) => {
+                        tx.prepare_cached(queries::MERGE_FILE_TAGS)?
+                            .execute([id, existing])?;
+                        tx.prepare_cached(queries::DELETE_FILE_TAGS)?
+                            .execute([id])?;
+                        tx.prepare_cached(queries::DELETE_FILE)?.execute([id])?;
+                        relocation.merged += 1;
+                    }
+                    None => {
+                        tx.prepare_cached(queries::MOVE_FILE)?
+                            .execute(params![id, target])?;
+                    }
+                }
+                relocation.moved += 1;
+            }
+
+            Ok(relocation)
+        })
+    }
+
+    fn get_tags(&self, path: &str) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(queries::GET_FILE_TAGS)?;
+
+        let tags = stmt
+            .query_map([path], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+
+        Ok(tags)
+    }
+
+    fn tags_under(&self, dir: &str) -> Result<Vec<(String, String)>> {
+        let (start, end) = descendant_range(dir);
+        let mut stmt = self.conn.prepare(queries::TAGS_UNDER)?;
+
+        let pairs = stmt
+            .query_map(params![dir.trim_end_matches('/'), start, end], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(pairs)
+    }
+
+    fn list_tagged(&self, tag: &str) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(queries::LIST_TAGS)?;
+
+        let paths = stmt
+            .query_map([tag], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+
+        Ok(paths)
+    }
+
+    fn search(&self, query: &Query) -> Result<Vec<String>> {
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let (sql, params) = Self::search_sql(query);
+        let mut stmt = self.conn.prepare(&sql)?;
+
+        let paths = stmt
+            .query_map(params_from_iter(params), |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+
+        Ok(paths)
+    }
+}
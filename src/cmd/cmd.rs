@@ -1,18 +1,30 @@
-use clap::{Parser, Subcommand};
+use clap::{builder::FalseyValueParser, Args, Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
 #[derive(Parser)]
 pub struct Cli {
+    #[command(flatten)]
+    pub store: StoreArgs,
     #[command(subcommand)]
     pub command: Commands,
 }
 
+/// Which store commands work on.
+#[derive(Args)]
+pub struct StoreArgs {
+    /// Named store to use instead of the default one, see `stag store`
+    #[clap(long, global = true, env = "STAG_STORE")]
+    pub store: Option<String>,
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     #[command(alias = "a")]
     Add(Add),
     #[command(alias = "rm")]
     Remove(Remove),
+    Untag(Untag),
+    Relocate(Relocate),
     #[command(alias = "ls")]
     List(List),
     #[command(alias = "s")]
@@ -21,6 +33,38 @@ pub enum Commands {
     Autotag(Autotag),
     #[command(alias = "i")]
     Inspect(Inspect),
+    Rules(Rules),
+    Init(Init),
+    Store(Store),
+    Xattr(Xattr),
+    Import(Import),
+    Sidecar(Sidecar),
+    Frontmatter(Frontmatter),
+    View(View),
+}
+
+/// How paths given on the command line get walked.
+#[derive(Args, Clone, Copy)]
+pub struct WalkArgs {
+    #[clap(short, long)]
+    pub recursive: bool,
+    #[clap(long)] // FIX: Think of a good short bind that doesn't overlap help
+    pub hidden: bool,
+    /// Walker threads for recursive operations, 0 picks one per core
+    #[clap(short = 'j', long, default_value_t = 0)]
+    pub threads: usize,
+}
+
+/// Where search, list and inspect look and how they print what they find.
+#[derive(Args, Clone, Copy)]
+pub struct FederationArgs {
+    /// Also look in every portable store above the working directory and the
+    /// ones listed under `projects` in the config
+    #[clap(long)]
+    pub federated: bool,
+    /// Print JSON, with the stores every result came from
+    #[clap(long)]
+    pub json: bool,
 }
 
 #[derive(Parser)]
@@ -28,26 +72,74 @@ pub struct Add {
     pub tag: String,
     #[clap(required = true, num_args = 1..)]
     pub paths: Vec<PathBuf>,
+    #[command(flatten)]
+    pub walk: WalkArgs,
+    /// Print what would change (`+tag path` / `-tag path`) without changing anything
+    #[clap(short = 'n', long)]
+    pub dry_run: bool,
+    /// Don't print progress or the summary
     #[clap(short, long)]
-    pub recursive: bool,
-    #[clap(long)] // FIX: Think of a good short bind that doesn't overlap help
-    pub hidden: bool,
+    pub quiet: bool,
+    /// Keep the `user.xdg.tags` extended attribute in sync
+    #[clap(long, env = "STAG_XATTR_SYNC", value_parser = FalseyValueParser::new())]
+    pub xattr: bool,
 }
 
 #[derive(Parser)]
 pub struct Remove {
     pub tag: String,
-    #[clap(required = true, num_args = 1..)]
+    #[clap(required_unless_present = "under", num_args = 1..)]
+    pub paths: Vec<PathBuf>,
+    /// Untag everything stored below this directory instead, without walking it
+    #[clap(long, conflicts_with_all = ["paths", "recursive", "hidden", "threads"])]
+    pub under: Option<PathBuf>,
+    #[command(flatten)]
+    pub walk: WalkArgs,
+    /// Print what would change (`+tag path` / `-tag path`) without changing anything
+    #[clap(short = 'n', long)]
+    pub dry_run: bool,
+    /// Don't print progress or the summary
+    #[clap(short, long)]
+    pub quiet: bool,
+    /// Keep the `user.xdg.tags` extended attribute in sync
+    #[clap(long, env = "STAG_XATTR_SYNC", value_parser = FalseyValueParser::new())]
+    pub xattr: bool,
+}
+
+/// Remove every tag from paths, even ones that no longer exist
+#[derive(Parser)]
+pub struct Untag {
+    #[clap(required_unless_present = "under", num_args = 1..)]
     pub paths: Vec<PathBuf>,
+    /// Untag everything stored below this directory, same as `untag DIR -r`
+    #[clap(long, conflicts_with = "paths")]
+    pub under: Option<PathBuf>,
+    /// Also untag everything stored below the given directories
     #[clap(short, long)]
     pub recursive: bool,
-    #[clap(long)] // FIX: Think of a good short bind that doesn't overlap help
-    pub hidden: bool,
+    /// Drop the paths from the store entirely
+    #[clap(long)]
+    pub forget: bool,
+    /// Don't print the summary
+    #[clap(short, long)]
+    pub quiet: bool,
+}
+
+/// Point stored paths at a directory tree's new location after moving it
+#[derive(Parser)]
+pub struct Relocate {
+    pub from: PathBuf,
+    pub to: PathBuf,
 }
 
 #[derive(Parser)]
 pub struct List {
     pub tag: String,
+    #[command(flatten)]
+    pub federation: FederationArgs,
+    /// Only stored paths at or below this directory, it doesn't have to exist
+    #[clap(long)]
+    pub under: Option<PathBuf>,
     #[clap(long)]
     pub dirs: bool,
     #[clap(long)]
@@ -58,6 +150,8 @@ pub struct List {
 pub struct Search {
     #[clap(required = true, num_args = 1..)]
     pub tags: Vec<String>,
+    #[command(flatten)]
+    pub federation: FederationArgs,
     #[clap(long)]
     pub any: bool,
     #[clap(long)]
@@ -66,24 +160,184 @@ pub struct Search {
     pub files: bool,
     #[clap(short, long, num_args = 1..)]
     pub exclude: Vec<String>,
+    /// Only stored paths at or below this directory, it doesn't have to exist
+    #[clap(long)]
+    pub under: Option<PathBuf>,
+    /// Search these named stores instead, each result prefixed with its store
+    #[clap(long, value_delimiter = ',', num_args = 1.., conflicts_with = "federated")]
+    pub stores: Vec<String>,
+    /// Search every named store, like `--stores` with all of them
+    #[clap(long, conflicts_with_all = ["stores", "federated"])]
+    pub all_stores: bool,
 }
 
 #[derive(Parser)]
 pub struct Autotag {
     #[clap(required = true, num_args = 1..)]
     pub paths: Vec<PathBuf>,
+    #[command(flatten)]
+    pub walk: WalkArgs,
+    /// Print the tags each path would get without changing anything
+    #[clap(short = 'n', long, visible_alias = "preview", short_alias = 'p')]
+    pub dry_run: bool,
+    /// Don't print progress or the summary
     #[clap(short, long)]
-    pub recursive: bool,
-    #[clap(short, long)]
-    pub preview: bool,
-    #[clap(long)] // FIX: Think of a good short bind that doesn't overlap help
-    pub hidden: bool,
+    pub quiet: bool,
+}
+
+/// Autotag rules from `[[autotag.rules]]` in the config
+#[derive(Parser)]
+pub struct Rules {
+    #[command(subcommand)]
+    pub action: RulesAction,
+}
+
+#[derive(Subcommand)]
+pub enum RulesAction {
+    /// Show which rules match a path and the tags they'd add
+    Test { path: PathBuf },
+}
+
+/// Create a portable store at the root of a tree, paths in it are stored relative
+/// to the root. Commands run anywhere below it use it automatically.
+#[derive(Parser)]
+pub struct Init {
+    #[clap(default_value = ".")]
+    pub dir: PathBuf,
+}
+
+/// Manage named stores, ie. to keep work and personal tags apart
+#[derive(Parser)]
+pub struct Store {
+    #[command(subcommand)]
+    pub action: StoreAction,
+}
+
+#[derive(Subcommand)]
+pub enum StoreAction {
+    /// List stores, the one in use is marked with `*`
+    #[command(alias = "ls")]
+    List,
+    Create {
+        name: String,
+    },
+    /// Delete a store and every tag in it
+    #[command(alias = "rm")]
+    Delete {
+        name: String,
+    },
 }
 
 #[derive(Parser)]
 pub struct Inspect {
     #[clap(required = true, num_args = 1..)]
     pub paths: Vec<PathBuf>,
+    #[command(flatten)]
+    pub federation: FederationArgs,
     #[clap(short, long)]
     pub verbose: bool,
 }
+
+#[derive(Parser)]
+pub struct Xattr {
+    #[command(subcommand)]
+    pub action: XattrAction,
+}
+
+#[derive(Subcommand)]
+pub enum XattrAction {
+    /// Write stored tags into `user.xdg.tags`
+    Push(XattrSync),
+    /// Read tags from `user.xdg.tags` into the store
+    Pull(XattrSync),
+}
+
+#[derive(Parser)]
+pub struct XattrSync {
+    #[clap(required = true, num_args = 1..)]
+    pub paths: Vec<PathBuf>,
+    #[command(flatten)]
+    pub walk: WalkArgs,
+}
+
+#[derive(Parser)]
+pub struct Import {
+    #[clap(long, value_enum)]
+    pub from: ImportSource,
+    /// Path to the database to import from
+    pub db: PathBuf,
+    /// Directory relative paths in the database are under, defaults to the
+    /// parent of `.tmsu`
+    #[clap(long)]
+    pub root: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ImportSource {
+    Tmsu,
+}
+
+/// Share tags through `.stag.toml` files committed next to the tagged paths
+#[derive(Parser)]
+pub struct Sidecar {
+    #[command(subcommand)]
+    pub action: SidecarAction,
+}
+
+#[derive(Subcommand)]
+pub enum SidecarAction {
+    /// Write the stored tags of a directory and everything below it into `DIR/.stag.toml`
+    Export { dir: PathBuf },
+    /// Read tags from `DIR/.stag.toml` into the store
+    Import {
+        dir: PathBuf,
+        /// Also read every `.stag.toml` below the directory
+        #[clap(short, long)]
+        recursive: bool,
+    },
+}
+
+/// Sync tags with the `tags:` frontmatter of Markdown notes (Obsidian style)
+#[derive(Parser)]
+pub struct Frontmatter {
+    #[command(subcommand)]
+    pub action: FrontmatterAction,
+}
+
+#[derive(Subcommand)]
+pub enum FrontmatterAction {
+    /// Read frontmatter tags into the store
+    Pull(FrontmatterSync),
+    /// Write stored tags into the frontmatter
+    Push(FrontmatterSync),
+}
+
+#[derive(Parser)]
+pub struct FrontmatterSync {
+    pub dir: PathBuf,
+    #[clap(short, long)]
+    pub recursive: bool,
+    /// Also sync notes where both sides changed since the last sync
+    #[clap(long)]
+    pub force: bool,
+}
+
+#[derive(Parser)]
+pub struct View {
+    /// Directory to fill with symlinks to the search results
+    pub dir: PathBuf,
+    #[clap(num_args = 1.., required_unless_present = "refresh")]
+    pub tags: Vec<String>,
+    #[clap(long)]
+    pub any: bool,
+    #[clap(short, long, num_args = 1..)]
+    pub exclude: Vec<String>,
+    /// Rebuild an existing view from its saved query
+    #[clap(long, conflicts_with_all = ["tags", "any", "exclude", "nested"])]
+    pub refresh: bool,
+    /// Also lay out results in one folder per tag (`view/rust/proj/...`)
+    #[clap(long)]
+    pub nested: bool,
+    #[clap(long, default_value_t = 2, requires = "nested")]
+    pub depth: usize,
+}
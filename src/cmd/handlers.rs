@@ -1,48 +1,172 @@
-use crate::{autotag::autotag_paths, tagstore::TagStore};
+use std::{collections::BTreeMap, path::PathBuf};
+
 use anyhow::{anyhow, Result};
+use serde_json::json;
+use stag::{
+    autotag::{autotag_paths, builtin_tags, diff_autotags, plan_autotags},
+    federated::Hit,
+    frontmatter::{sync_frontmatter, Direction},
+    import::{import_tmsu, ImportReport},
+    sidecar::{export_sidecar, import_sidecars, SIDECAR_FILE},
+    stores::{create_store, delete_store, list_stores, search_stores, DEFAULT_STORE},
+    tagstore::PORTABLE_DIR,
+    view::{build_view, ViewQuery},
+    xattrs::{pull_paths, push_paths, sync_each, update_tag},
+    Config, Error, Progress, Query, RuleSet, TagChange, TagStore,
+};
 
 use super::{
-    utils::{filter_paths, handle_paths, print_paths, PathAction},
-    Add, Autotag, Inspect, List, Remove, Search,
+    progress::{print_summary, ProgressDisplay},
+    utils::{
+        filter_paths, handle_paths, is_kind, preview_paths, print_changes, print_hits, print_paths,
+        report_sync_failures, PathAction,
+    },
+    Add, Autotag, Frontmatter, FrontmatterAction, Import, ImportSource, Init, Inspect, List,
+    Relocate, Remove, Rules, RulesAction, Search, Sidecar, SidecarAction, Store, StoreAction,
+    StoreArgs, Untag, View, Xattr, XattrAction,
 };
 
 impl Add {
-    pub fn run(&self) -> Result<()> {
-        let mut store = TagStore::new()?;
+    pub fn run(&self, store: &StoreArgs) -> Result<()> {
+        let mut store = store.open()?;
+
+        if self.dry_run {
+            return preview_paths(
+                &store,
+                &self.tag,
+                self.paths.clone(),
+                PathAction::Add,
+                self.walk,
+            );
+        }
+
         handle_paths(
             &mut store,
             &self.tag,
             self.paths.clone(),
             PathAction::Add,
-            self.recursive,
-            self.hidden,
+            self.walk,
+            self.xattr,
+            self.quiet,
         )
     }
 }
 
 impl Remove {
-    pub fn run(&self) -> Result<()> {
-        let mut store = TagStore::new()?;
+    pub fn run(&self, store: &StoreArgs) -> Result<()> {
+        let mut store = store.open()?;
+
+        if let Some(dir) = &self.under {
+            if self.dry_run {
+                let query = Query::new().tag(&self.tag).under(dir);
+                let mut changes: Vec<TagChange> = store
+                    .search(&query)?
+                    .into_iter()
+                    .map(|path| TagChange {
+                        path,
+                        tag: self.tag.clone(),
+                        added: false,
+                    })
+                    .collect();
+
+                changes.sort();
+                print_changes(&changes);
+                return Ok(());
+            }
+
+            // Find what to sync before the tags are gone, deleted paths have nothing to sync
+            let synced: Vec<_> = match self.xattr {
+                true => store
+                    .search(&Query::new().tag(&self.tag).under(dir))?
+                    .into_iter()
+                    .filter(|path| path.symlink_metadata().is_ok())
+                    .collect(),
+                false => Vec::new(),
+            };
+
+            let changes = store.remove_tags_under(dir, &self.tag)?;
+            print_summary(true, changes, &Progress::new(), self.quiet);
+            return report_sync_failures(sync_each(&synced, |path| {
+                update_tag(path, &self.tag, false)
+            }));
+        }
+
+        if self.dry_run {
+            return preview_paths(
+                &store,
+                &self.tag,
+                self.paths.clone(),
+                PathAction::Remove,
+                self.walk,
+            );
+        }
+
         handle_paths(
             &mut store,
             &self.tag,
             self.paths.clone(),
             PathAction::Remove,
-            self.recursive,
-            self.hidden,
+            self.walk,
+            self.xattr,
+            self.quiet,
         )
     }
 }
 
+impl Untag {
+    pub fn run(&self, store: &StoreArgs) -> Result<()> {
+        let mut store = store.open()?;
+        let removed = match &self.under {
+            Some(dir) => store.untag(std::slice::from_ref(dir), true, self.forget)?,
+            None => store.untag(&self.paths, self.recursive, self.forget)?,
+        };
+
+        if !self.quiet {
+            eprintln!("Removed {} tag(s)", removed);
+        }
+
+        Ok(())
+    }
+}
+
+impl Relocate {
+    pub fn run(&self, store: &StoreArgs) -> Result<()> {
+        let mut store = store.open()?;
+        let relocation = store.relocate(&self.from, &self.to)?;
+
+        println!(
+            "Relocated {} path(s), {} merged into existing entries",
+            relocation.moved, relocation.merged
+        );
+
+        Ok(())
+    }
+}
+
 impl List {
-    pub fn run(&self) -> Result<()> {
+    pub fn run(&self, store: &StoreArgs) -> Result<()> {
         if self.dirs && self.files {
             return Err(anyhow!("Cannot specify both --dirs and --files"));
         };
 
-        let store = TagStore::new()?;
+        if let Some(federation) = store.open_federation(self.federation)? {
+            let mut query = Query::new().tag(&self.tag);
+            if let Some(dir) = &self.under {
+                query = query.under(dir);
+            }
+
+            let hits = federation.search(&query)?;
+            return print_hits(hits, self.federation.json, self.dirs, self.files);
+        }
 
-        if let Ok(paths) = store.list_tagged(&self.tag) {
+        let store = store.open()?;
+
+        let paths = match &self.under {
+            Some(dir) => store.search(&Query::new().tag(&self.tag).under(dir)),
+            None => store.list_tagged(&self.tag),
+        };
+
+        if let Ok(paths) = paths {
             print_paths(&filter_paths(paths, self.dirs, self.files));
         }
 
@@ -51,17 +175,56 @@ impl List {
 }
 
 impl Search {
-    pub fn run(&self) -> Result<()> {
+    pub fn run(&self, store: &StoreArgs) -> Result<()> {
         if self.dirs && self.files {
             return Err(anyhow!("Cannot specify both --dirs and --files"));
         };
 
-        let store = TagStore::new()?;
+        let mut query = Query::new()
+            .tags(self.tags.iter().cloned())
+            .excludes(self.exclude.iter().cloned())
+            .any(self.any);
 
-        let include_tags: Vec<&str> = self.tags.iter().map(|s| s.as_str()).collect();
-        let exclude_tags: Vec<&str> = self.exclude.iter().map(|s| s.as_str()).collect();
+        if let Some(dir) = &self.under {
+            query = query.under(dir);
+        }
+
+        let names = if self.all_stores {
+            list_stores()?
+        } else {
+            self.stores.clone()
+        };
+
+        if !names.is_empty() {
+            let results = search_stores(&names, &query)?;
+
+            if self.federation.json {
+                // Same shape as federated results, one hit per path with all its stores
+                let mut stores: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+                for (name, path) in results {
+                    stores.entry(path).or_default().push(name);
+                }
+                let hits = stores
+                    .into_iter()
+                    .map(|(path, stores)| Hit { path, stores })
+                    .collect();
+                return print_hits(hits, true, self.dirs, self.files);
+            }
 
-        if let Ok(paths) = store.search_tags(&include_tags, &exclude_tags, self.any) {
+            for (name, path) in results {
+                if is_kind(&path, self.dirs, self.files) {
+                    println!("{}\t{}", name, path.display());
+                }
+            }
+            return Ok(());
+        }
+
+        if let Some(federation) = store.open_federation(self.federation)? {
+            let hits = federation.search(&query)?;
+            return print_hits(hits, self.federation.json, self.dirs, self.files);
+        }
+
+        if let Ok(paths) = store.open()?.search(&query) {
             print_paths(&filter_paths(paths, self.dirs, self.files));
         }
 
@@ -70,35 +233,293 @@ impl Search {
 }
 
 impl Autotag {
-    pub fn run(&self) -> Result<()> {
-        let mut store = TagStore::new()?;
+    pub fn run(&self, store: &StoreArgs) -> Result<()> {
+        let mut store = store.open()?;
+        let rules = RuleSet::new(&Config::load()?.autotag)?;
+
+        if self.dry_run {
+            let plan = plan_autotags(
+                self.paths.clone(),
+                self.walk.recursive,
+                self.walk.hidden,
+                self.walk.threads,
+                &rules,
+                &Progress::new(),
+            )?;
+            print_changes(&diff_autotags(&store, &plan)?);
 
-        autotag_paths(
+            return Ok(());
+        }
+
+        let progress = Progress::new();
+        let display = ProgressDisplay::start(&progress, self.quiet);
+
+        let changes = autotag_paths(
             &mut store,
             self.paths.clone(),
-            self.preview, // TODO: Possibly remove? Just messing with something
-            self.recursive,
-            self.hidden,
+            self.walk.recursive,
+            self.walk.hidden,
+            self.walk.threads,
+            &rules,
+            &progress,
         )?;
 
+        drop(display);
+        print_summary(false, changes, &progress, self.quiet);
+
         Ok(())
     }
 }
 
 impl Inspect {
-    pub fn run(&self) -> Result<()> {
-        let store = TagStore::new()?;
+    pub fn run(&self, store: &StoreArgs) -> Result<()> {
+        if let Some(federation) = store.open_federation(self.federation)? {
+            let mut files = Vec::new();
+
+            for path in &self.paths {
+                let tags = federation.get_file_tags(path)?;
+                if tags.is_empty() && !path.exists() {
+                    return Err(Error::PathNotFound(path.clone()).into());
+                }
+
+                if self.federation.json {
+                    let path = path.canonicalize().unwrap_or(path.clone());
+                    files.push(json!({ "path": path, "tags": tags }));
+                    continue;
+                }
 
-        for path in &self.paths {
-            if let Ok(tags) = store.get_file_tags(path) {
                 if self.verbose {
                     print!("{}: ", path.display());
                 }
+                let names: Vec<&str> = tags.iter().map(|t| t.tag.as_str()).collect();
+                println!("{}", names.join(", "));
+            }
+
+            if self.federation.json {
+                println!("{}", serde_json::to_string_pretty(&files)?);
+            }
+            return Ok(());
+        }
+
+        let store = store.open()?;
+
+        for path in &self.paths {
+            let tags = store.get_file_tags(path)?;
+            // Gone paths are fine as long as something is stored for them
+            if tags.is_empty() && !path.exists() {
+                return Err(Error::PathNotFound(path.clone()).into());
+            }
 
-                println!("{}", tags.join(", "));
+            if self.verbose {
+                print!("{}: ", path.display());
             }
+            println!("{}", tags.join(", "));
         }
 
         Ok(())
     }
 }
+
+impl Rules {
+    pub fn run(&self) -> Result<()> {
+        let RulesAction::Test { path } = &self.action;
+        let rules = RuleSet::new(&Config::load()?.autotag)?;
+        let metadata = std::fs::metadata(path).map_err(Error::file(path))?;
+
+        let matches = rules.matching(&metadata, path);
+        for rule in &matches {
+            println!("{}: {}", rule.name, rule.tags.join(", "));
+        }
+
+        if rules.builtin() {
+            let tags = builtin_tags(path, &rules)?;
+            println!("builtin: {}", tags.join(", "));
+        } else if matches.is_empty() {
+            eprintln!("No rules matched");
+        }
+
+        Ok(())
+    }
+}
+
+impl Init {
+    pub fn run(&self) -> Result<()> {
+        let store = TagStore::open_portable(&self.dir)?;
+        let root = store.root().expect("portable stores have a root");
+
+        println!(
+            "Initialized portable store in {}",
+            root.join(PORTABLE_DIR).display()
+        );
+
+        Ok(())
+    }
+}
+
+impl Store {
+    pub fn run(&self, store: &StoreArgs) -> Result<()> {
+        match &self.action {
+            StoreAction::List => {
+                let current = match &store.store {
+                    Some(name) => name.clone(),
+                    None => Config::load()?.store.unwrap_or(DEFAULT_STORE.to_string()),
+                };
+
+                for name in list_stores()? {
+                    let marker = if name == current { '*' } else { ' ' };
+                    println!("{} {}", marker, name);
+                }
+            }
+            StoreAction::Create { name } => {
+                create_store(name)?;
+                println!("Created store {}", name);
+            }
+            StoreAction::Delete { name } => {
+                delete_store(name)?;
+                println!("Deleted store {}", name);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Xattr {
+    pub fn run(&self, store: &StoreArgs) -> Result<()> {
+        let mut store = store.open()?;
+
+        let failures = match &self.action {
+            XattrAction::Push(args) => {
+                let walk = args.walk;
+                push_paths(
+                    &store,
+                    args.paths.clone(),
+                    walk.recursive,
+                    walk.hidden,
+                    walk.threads,
+                )?
+            }
+            XattrAction::Pull(args) => {
+                let walk = args.walk;
+                pull_paths(
+                    &mut store,
+                    args.paths.clone(),
+                    walk.recursive,
+                    walk.hidden,
+                    walk.threads,
+                )?
+            }
+        };
+
+        report_sync_failures(failures)
+    }
+}
+
+impl Import {
+    pub fn run(&self, store: &StoreArgs) -> Result<()> {
+        let mut store = store.open()?;
+
+        let report = match self.from {
+            ImportSource::Tmsu => import_tmsu(&mut store, &self.db, self.root.as_deref())?,
+        };
+
+        print_import_report(&report);
+        Ok(())
+    }
+}
+
+fn print_import_report(report: &ImportReport) {
+    for path in &report.missing {
+        eprintln!("Missing, not imported: {}", path.display());
+    }
+
+    println!(
+        "Imported {} tag association(s), skipped {} missing path(s)",
+        report.imported,
+        report.missing.len()
+    );
+}
+
+impl Sidecar {
+    pub fn run(&self, store: &StoreArgs) -> Result<()> {
+        let mut store = store.open()?;
+
+        match &self.action {
+            SidecarAction::Export { dir } => {
+                let exported = export_sidecar(&store, dir)?;
+                println!(
+                    "Exported {} path(s) to {}",
+                    exported,
+                    dir.join(SIDECAR_FILE).display()
+                );
+            }
+            SidecarAction::Import { dir, recursive } => {
+                let import = import_sidecars(&mut store, dir, *recursive)?;
+                for path in &import.outside {
+                    eprintln!(
+                        "Outside the sidecar's directory, not imported: {}",
+                        path.display()
+                    );
+                }
+                print_import_report(&import.report);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Frontmatter {
+    pub fn run(&self, store: &StoreArgs) -> Result<()> {
+        let mut store = store.open()?;
+
+        let (args, direction) = match &self.action {
+            FrontmatterAction::Pull(args) => (args, Direction::Pull),
+            FrontmatterAction::Push(args) => (args, Direction::Push),
+        };
+        let report =
+            sync_frontmatter(&mut store, &args.dir, args.recursive, direction, args.force)?;
+
+        for path in &report.unparseable {
+            eprintln!("Skipped {}, can't parse its `tags:` entry", path.display());
+        }
+        for conflict in &report.conflicts {
+            eprintln!("Conflict: {}", conflict.path.display());
+            eprintln!("  frontmatter: {}", conflict.frontmatter.join(", "));
+            eprintln!("  stag:        {}", conflict.store.join(", "));
+        }
+
+        println!("Updated {} file(s)", report.updated);
+        if !report.conflicts.is_empty() {
+            eprintln!(
+                "Skipped {} conflict(s) where both sides changed since the last sync, \
+                 rerun with --force to sync them anyway",
+                report.conflicts.len()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl View {
+    pub fn run(&self, store: &StoreArgs) -> Result<()> {
+        let store = store.open()?;
+
+        let query = if self.refresh {
+            ViewQuery::load(&self.dir)?
+        } else {
+            ViewQuery {
+                tags: self.tags.clone(),
+                exclude: self.exclude.clone(),
+                any: self.any,
+                nested: self.nested.then_some(self.depth),
+            }
+        };
+
+        let report = build_view(&store, &self.dir, &query)?;
+        println!("{} link(s) added, {} removed", report.added, report.removed);
+
+        Ok(())
+    }
+}
@@ -1,25 +1,36 @@
+#[allow(clippy::module_inception)]
 mod cmd;
 mod handlers;
+mod progress;
 mod utils;
 
 use anyhow::Result;
 
 pub use cmd::*;
-pub use utils::collect_paths;
 
 pub trait Run {
-    fn run(&self) -> Result<()>;
+    fn run(&self, store: &StoreArgs) -> Result<()>;
 }
 
 impl Run for Commands {
-    fn run(&self) -> Result<()> {
+    fn run(&self, store: &StoreArgs) -> Result<()> {
         match self {
-            Commands::Add(cmd) => cmd.run(),
-            Commands::Remove(cmd) => cmd.run(),
-            Commands::List(cmd) => cmd.run(),
-            Commands::Search(cmd) => cmd.run(),
-            Commands::Autotag(cmd) => cmd.run(),
-            Commands::Inspect(cmd) => cmd.run(),
+            Commands::Add(cmd) => cmd.run(store),
+            Commands::Remove(cmd) => cmd.run(store),
+            Commands::Untag(cmd) => cmd.run(store),
+            Commands::Relocate(cmd) => cmd.run(store),
+            Commands::List(cmd) => cmd.run(store),
+            Commands::Search(cmd) => cmd.run(store),
+            Commands::Autotag(cmd) => cmd.run(store),
+            Commands::Inspect(cmd) => cmd.run(store),
+            Commands::Rules(cmd) => cmd.run(),
+            Commands::Init(cmd) => cmd.run(),
+            Commands::Store(cmd) => cmd.run(store),
+            Commands::Xattr(cmd) => cmd.run(store),
+            Commands::Import(cmd) => cmd.run(store),
+            Commands::Sidecar(cmd) => cmd.run(store),
+            Commands::Frontmatter(cmd) => cmd.run(store),
+            Commands::View(cmd) => cmd.run(store),
         }
     }
 }
@@ -0,0 +1,79 @@
+use std::{
+    io::IsTerminal,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use stag::{Changes, Progress};
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Redraws the walk counters on stderr while a long operation runs.
+/// Only when stderr is a terminal, scripts and pipes never see it.
+pub(crate) struct ProgressDisplay {
+    done: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ProgressDisplay {
+    pub(crate) fn start(progress: &Progress, quiet: bool) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+
+        let handle = (!quiet && std::io::stderr().is_terminal()).then(|| {
+            let progress = progress.clone();
+            let done = done.clone();
+
+            thread::spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    eprint!(
+                        "\r\x1b[2K{} walked, {} processed, {} errors",
+                        progress.walked(),
+                        progress.processed(),
+                        progress.skipped()
+                    );
+                    thread::sleep(REDRAW_INTERVAL);
+                }
+                eprint!("\r\x1b[2K");
+            })
+        });
+
+        Self { done, handle }
+    }
+}
+
+// Also runs when the operation bails with `?`, so the error isn't printed mid-line
+impl Drop for ProgressDisplay {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Prints what an add/rm/autotag did to stderr, unless `quiet`.
+pub(crate) fn print_summary(removed: bool, changes: Changes, progress: &Progress, quiet: bool) {
+    if quiet {
+        return;
+    }
+
+    if removed {
+        eprintln!(
+            "Removed {} tag(s), {} weren't tagged, {} skipped",
+            changes.changed,
+            changes.unchanged,
+            progress.skipped()
+        );
+    } else {
+        eprintln!(
+            "Added {} tag(s), {} already tagged, {} skipped",
+            changes.changed,
+            changes.unchanged,
+            progress.skipped()
+        );
+    }
+}
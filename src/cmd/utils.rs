@@ -1,9 +1,18 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
-use ignore::WalkBuilder;
+use anyhow::{anyhow, Result};
+use stag::{
+    federated::Hit,
+    stores::DEFAULT_STORE,
+    walk_map, walk_paths,
+    xattrs::{sync_each, update_tag, SyncFailures},
+    Config, Federation, Progress, TagChange, TagStore,
+};
 
-use crate::tagstore::TagStore;
+use super::{
+    progress::{print_summary, ProgressDisplay},
+    FederationArgs, StoreArgs, WalkArgs,
+};
 
 // FIX: This entire file could use some love <3
 
@@ -18,47 +27,99 @@ pub(crate) enum PathAction {
     Remove,
 }
 
-/// Collects paths based on the given options (recursive, hidden).
-pub fn collect_paths(paths: Vec<PathBuf>, recursive: bool, hidden: bool) -> Result<Vec<PathBuf>> {
-    // FIX: Document this better
-    // NOTE: Hidden flag only applies for recursive indexing
-    // It doesn't really make sense if someone does ie.
-    // `stag a tag .hidden` and it doesn't index.
-    // Hidden is more for:
-    // `stag a config .config -r --hidden`, which will now recurse
-    // .config and add ALL files no matter ignore-rules
-    let collected_paths: Vec<PathBuf> = if recursive {
-        paths
-            .iter()
-            .flat_map(|path_pattern| {
-                WalkBuilder::new(path_pattern)
-                    .hidden(!hidden)
-                    .build()
-                    .filter_map(Result::ok)
-                    .map(|entry| entry.path().to_path_buf())
-                    .collect::<Vec<_>>()
-            })
-            .collect()
-    } else {
-        paths.into_iter().collect()
+pub(crate) fn handle_paths(
+    store: &mut TagStore,
+    tag: &str,
+    paths: Vec<PathBuf>,
+    action: PathAction,
+    walk: WalkArgs,
+    xattr: bool,
+    quiet: bool,
+) -> Result<()> {
+    let progress = Progress::new();
+    let display = ProgressDisplay::start(&progress, quiet);
+
+    // Straight from the walker(s) into the store, huge trees never sit in memory
+    let walked = progress.track(walk_map(
+        paths,
+        walk.recursive,
+        walk.hidden,
+        walk.threads,
+        &progress,
+        Some,
+    ));
+
+    if !xattr {
+        let changes = match action {
+            PathAction::Add => store.add_tags_stream(walked, tag)?,
+            PathAction::Remove => store.remove_tags_stream(walked, tag)?,
+        };
+
+        drop(display);
+        print_summary(
+            matches!(action, PathAction::Remove),
+            changes,
+            &progress,
+            quiet,
+        );
+        return Ok(());
+    }
+
+    // Syncing needs the paths again afterwards
+    let paths: Vec<PathBuf> = walked.collect();
+
+    let changes = match action {
+        PathAction::Add => store.add_tags_batch(&paths, tag)?,
+        PathAction::Remove => store.remove_tags_batch(&paths, tag)?,
     };
 
-    Ok(collected_paths)
+    drop(display);
+    print_summary(
+        matches!(action, PathAction::Remove),
+        changes,
+        &progress,
+        quiet,
+    );
+
+    let add = matches!(action, PathAction::Add);
+    report_sync_failures(sync_each(&paths, |path| update_tag(path, tag, add)))
 }
 
-pub(crate) fn handle_paths(
-    store: &mut TagStore,
+/// Dry run of [`handle_paths`], prints the changes it would make.
+pub(crate) fn preview_paths(
+    store: &TagStore,
     tag: &str,
     paths: Vec<PathBuf>,
     action: PathAction,
-    recursive: bool,
-    hidden: bool,
+    walk: WalkArgs,
 ) -> Result<()> {
-    let paths = collect_paths(paths, recursive, hidden)?;
+    let walked = walk_paths(paths, walk.recursive, walk.hidden, walk.threads);
+    let mut changes = store.diff_tags(walked, tag, matches!(action, PathAction::Add))?;
+
+    // Parallel walks come back in any order
+    changes.sort();
+    print_changes(&changes);
+
+    Ok(())
+}
+
+pub(crate) fn print_changes(changes: &[TagChange]) {
+    for change in changes {
+        println!("{}", change);
+    }
+}
+
+/// Prints every path that failed to sync and fails if there were any.
+pub(crate) fn report_sync_failures(failures: SyncFailures) -> Result<()> {
+    for (path, err) in &failures {
+        eprintln!("{}: {}", path.display(), err);
+    }
 
-    match action {
-        PathAction::Add => store.add_tags_batch(&paths, &tag)?,
-        PathAction::Remove => store.remove_tags_batch(&paths, &tag)?,
+    if !failures.is_empty() {
+        return Err(anyhow!(
+            "Failed to sync extended attributes for {} path(s)",
+            failures.len()
+        ));
     }
 
     Ok(())
@@ -71,14 +132,85 @@ pub(crate) fn filter_paths(paths: Vec<PathBuf>, dirs_only: bool, files_only: boo
 
     paths
         .into_iter()
-        .filter(|p| {
-            if dirs_only {
-                p.is_dir()
-            } else if files_only {
-                p.is_file()
-            } else {
-                true
-            }
-        })
+        .filter(|p| is_kind(p, dirs_only, files_only))
         .collect()
 }
+
+pub(crate) fn is_kind(path: &Path, dirs_only: bool, files_only: bool) -> bool {
+    if dirs_only {
+        path.is_dir()
+    } else if files_only {
+        path.is_file()
+    } else {
+        true
+    }
+}
+
+/// Prints federated results, as JSON or one path per line.
+pub(crate) fn print_hits(
+    hits: Vec<Hit>,
+    json: bool,
+    dirs_only: bool,
+    files_only: bool,
+) -> Result<()> {
+    let hits: Vec<Hit> = hits
+        .into_iter()
+        .filter(|hit| is_kind(&hit.path, dirs_only, files_only))
+        .collect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&hits)?);
+    } else {
+        for hit in hits {
+            println!("{}", hit.path.display());
+        }
+    }
+
+    Ok(())
+}
+
+impl StoreArgs {
+    /// The store picked with `--store`, or the default one, see [`TagStore::new`].
+    pub(crate) fn open(&self) -> Result<TagStore> {
+        Ok(match &self.store {
+            Some(name) => TagStore::open_named(name)?,
+            None => TagStore::new()?,
+        })
+    }
+
+    /// The stores search, list and inspect go through for `args`. `None` means plain
+    /// output from the single store [`StoreArgs::open`] picks.
+    pub(crate) fn open_federation(&self, args: FederationArgs) -> Result<Option<Federation>> {
+        let config = Config::load()?;
+        let federated = args.federated || config.federated;
+        if !federated && !args.json {
+            return Ok(None);
+        }
+
+        // `$STAG_DB_PATH` wins over the configured store, label what is actually queried
+        let default_label = || match std::env::var("STAG_DB_PATH") {
+            Ok(path) => path,
+            Err(_) => config.store.clone().unwrap_or(DEFAULT_STORE.to_string()),
+        };
+        let mut federation = Federation::new();
+
+        if federated {
+            let (label, global) = match &self.store {
+                Some(name) => (name.clone(), TagStore::open_named(name)?),
+                None => (default_label(), TagStore::open_global()?),
+            };
+            federation.add(label, global);
+            federation.discover(&std::env::current_dir()?.canonicalize()?, &config)?;
+        } else {
+            let store = self.open()?;
+            let label = match (store.root(), &self.store) {
+                (Some(root), _) => root.to_string_lossy().to_string(),
+                (None, Some(name)) => name.clone(),
+                (None, None) => default_label(),
+            };
+            federation.add(label, store);
+        }
+
+        Ok(Some(federation))
+    }
+}
@@ -0,0 +1,68 @@
+use std::{fs, io, path::PathBuf};
+
+use serde::Deserialize;
+
+use crate::{rules::AutotagConfig, stores::project_dirs, Error, Result};
+
+/// Settings from `config.toml` in the XDG config dir (`~/.config/stag/config.toml`).
+/// Everything is optional, a missing file is the same as an empty one.
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Named store to use when none is picked with `--store`
+    pub store: Option<String>,
+    /// Search, list and inspect every store around the working directory, as with `--federated`
+    pub federated: bool,
+    /// Roots of portable stores to federate with, on top of the ones found above the working directory
+    pub projects: Vec<PathBuf>,
+    /// Autotag rules, see [`crate::rules`]
+    pub autotag: AutotagConfig,
+}
+
+impl Config {
+    pub fn path() -> Result<PathBuf> {
+        Ok(project_dirs()?.config_dir().join("config.toml"))
+    }
+
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents).map_err(|source| Error::Config {
+                path,
+                source: Box::new(source),
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(Error::file(&path)(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config: Config = toml::from_str("store = \"work\"").unwrap();
+        assert_eq!(config.store.as_deref(), Some("work"));
+
+        let config: Config = toml::from_str("federated = true\nprojects = [\"/src/a\"]").unwrap();
+        assert!(config.federated);
+        assert_eq!(config.projects, vec![PathBuf::from("/src/a")]);
+
+        assert_eq!(toml::from_str::<Config>("").unwrap(), Config::default());
+        assert!(toml::from_str::<Config>("stroe = \"work\"").is_err());
+
+        let config: Config = toml::from_str(
+            "[autotag]\nbuiltin = false\n[[autotag.rules]]\ntags = [\"rust\"]\nmarker = \"Cargo.toml\"",
+        )
+        .unwrap();
+        assert!(!config.autotag.builtin);
+        assert_eq!(
+            config.autotag.rules[0].marker.as_deref(),
+            Some("Cargo.toml")
+        );
+        assert!(Config::default().autotag.builtin);
+    }
+}
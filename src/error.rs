@@ -0,0 +1,105 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Everything the library can fail with. The CLI wraps these in `anyhow`,
+/// library users can match on them.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Path does not exist: {}", .0.display())]
+    PathNotFound(PathBuf),
+
+    #[error("Failed to canonicalize path {}: {source}", .path.display())]
+    Canonicalize { path: PathBuf, source: io::Error },
+
+    #[error("Could not determine project directories")]
+    NoProjectDirs,
+
+    #[error("Failed to initialize the database schema: {0}")]
+    Schema(#[source] rusqlite::Error),
+
+    #[error("Extended attributes are not supported on this filesystem: {}", .0.display())]
+    XattrUnsupported(PathBuf),
+
+    #[error("Failed to access extended attributes of {}: {source}", .path.display())]
+    Xattr { path: PathBuf, source: io::Error },
+
+    #[error("Not a TMSU database (missing file/tag/file_tag tables): {}", .0.display())]
+    NotTmsu(PathBuf),
+
+    #[error("TMSU database {} has relative paths, pass --root", .0.display())]
+    TmsuRootUnknown(PathBuf),
+
+    #[error("Not a stag view: {}", .0.display())]
+    NotAView(PathBuf),
+
+    #[error("Invalid view manifest entry: {0}")]
+    InvalidViewManifest(String),
+
+    #[error("Refusing to turn non-empty directory into a view: {}", .0.display())]
+    ViewDirNotEmpty(PathBuf),
+
+    #[error("Can't relocate {} into itself ({})", .from.display(), .to.display())]
+    RelocateIntoItself { from: PathBuf, to: PathBuf },
+
+    #[error("{} is outside the portable store at {}", .path.display(), .root.display())]
+    OutsideRoot { path: PathBuf, root: PathBuf },
+
+    #[error("Invalid store name {0:?}, use letters, digits, `-` and `_`")]
+    InvalidStoreName(String),
+
+    #[error("No store named {0:?}, create it with `stag store create {0}`")]
+    StoreNotFound(String),
+
+    #[error("A store named {0:?} already exists")]
+    StoreExists(String),
+
+    #[error("The default store can't be deleted")]
+    DeleteDefaultStore,
+
+    #[error("Invalid config {}: {source}", .path.display())]
+    Config {
+        path: PathBuf,
+        source: Box<toml::de::Error>,
+    },
+
+    #[error("Invalid sidecar {}: {source}", .path.display())]
+    InvalidSidecar {
+        path: PathBuf,
+        source: Box<toml::de::Error>,
+    },
+
+    #[error("Invalid frontmatter sync state {}: {source}", .path.display())]
+    InvalidSyncBase {
+        path: PathBuf,
+        source: Box<toml::de::Error>,
+    },
+
+    #[error("Invalid STAG_BUSY_TIMEOUT {0:?}, expected milliseconds")]
+    InvalidBusyTimeout(String),
+
+    #[error("Invalid autotag rule {rule:?}: {reason}")]
+    InvalidRule { rule: String, reason: String },
+
+    #[error(transparent)]
+    Database(#[from] rusqlite::Error),
+
+    #[error("{}: {source}", .path.display())]
+    File { path: PathBuf, source: io::Error },
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl Error {
+    /// For `map_err` on file operations, so the error says which file it was.
+    pub fn file(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Self::File {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
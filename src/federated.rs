@@ -0,0 +1,113 @@
+//! Searching several stores as one, ie. the global store together with the
+//! portable stores of the projects around the working directory.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{
+    tagstore::{find_portable_roots, portable_db},
+    Config, Error, Query, Result, TagStore,
+};
+
+/// A path found in one or more stores.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Hit {
+    pub path: PathBuf,
+    pub stores: Vec<String>,
+}
+
+/// A tag of a path and the stores that have it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TagSource {
+    pub tag: String,
+    pub stores: Vec<String>,
+}
+
+/// Stores queried together. Every store has a label, the store name for
+/// named stores and the root for portable ones, which is what results list.
+#[derive(Default)]
+pub struct Federation {
+    stores: Vec<(String, TagStore)>,
+}
+
+impl Federation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, label: impl Into<String>, store: TagStore) {
+        self.stores.push((label.into(), store));
+    }
+
+    /// Adds the portable store of every tree at or above `start`, then the
+    /// ones listed under `projects` in the config. Roots already added are skipped,
+    /// so are projects without a store, searching shouldn't create one.
+    pub fn discover(&mut self, start: &Path, config: &Config) -> Result<()> {
+        let mut roots = find_portable_roots(start);
+        roots.extend(
+            config
+                .projects
+                .iter()
+                .filter_map(|root| root.canonicalize().ok())
+                .filter(|root| portable_db(root).is_file()),
+        );
+
+        for root in roots {
+            if self
+                .stores
+                .iter()
+                .any(|(_, store)| store.root() == Some(&root))
+            {
+                continue;
+            }
+
+            let store = TagStore::open_portable(&root)?;
+            self.add(root.to_string_lossy(), store);
+        }
+
+        Ok(())
+    }
+
+    /// Every store's results in one sorted list, paths found in several stores show up once.
+    pub fn search(&self, query: &Query) -> Result<Vec<Hit>> {
+        let mut hits: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+
+        for (label, store) in &self.stores {
+            for path in store.search(query)? {
+                hits.entry(path).or_default().push(label.clone());
+            }
+        }
+
+        Ok(hits
+            .into_iter()
+            .map(|(path, stores)| Hit { path, stores })
+            .collect())
+    }
+
+    /// The tags of `path` across all stores, sorted by tag.
+    pub fn get_file_tags(&self, path: &Path) -> Result<Vec<TagSource>> {
+        let mut tags: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+        for (label, store) in &self.stores {
+            let found = match store.get_file_tags(path) {
+                Ok(found) => found,
+                // Not a path this project's store can hold
+                Err(Error::OutsideRoot { .. }) => continue,
+                Err(err) => return Err(err),
+            };
+
+            for tag in found {
+                tags.entry(tag).or_default().push(label.clone());
+            }
+        }
+
+        Ok(tags
+            .into_iter()
+            .map(|(tag, stores)| TagSource { tag, stores })
+            .collect())
+    }
+}
@@ -0,0 +1,557 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use crate::{walk_paths, Error, Result, TagStore};
+
+/// File in a synced directory remembering the tags each side had after the last
+/// sync, which is how pull and push tell which side changed since.
+pub const FRONTMATTER_BASE: &str = ".stag-frontmatter.toml";
+
+const MARKDOWN_EXTENSIONS: &[&str] = &["md", "markdown"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Frontmatter into the store
+    Pull,
+    /// Store into the frontmatter
+    Push,
+}
+
+/// A file whose frontmatter and stored tags both changed since the last sync.
+#[derive(Debug, PartialEq)]
+pub struct Conflict {
+    pub path: PathBuf,
+    pub frontmatter: Vec<String>,
+    pub store: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    /// Files whose tags changed on the receiving side
+    pub updated: usize,
+    /// Files left alone since both sides changed, see `force`
+    pub conflicts: Vec<Conflict>,
+    /// Files left alone since their `tags:` entry couldn't be made sense of
+    pub unparseable: Vec<PathBuf>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Snapshot {
+    frontmatter: BTreeSet<String>,
+    store: BTreeSet<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Base {
+    files: BTreeMap<String, Snapshot>,
+}
+
+impl Base {
+    fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        toml::from_str(&fs::read_to_string(path).map_err(Error::file(path))?).map_err(|source| {
+            Error::InvalidSyncBase {
+                path: path.to_path_buf(),
+                source: Box::new(source),
+            }
+        })
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        let toml = toml::to_string(self).expect("sync bases always serialize");
+        fs::write(path, toml).map_err(Error::file(path))?;
+        Ok(())
+    }
+}
+
+/// Syncs the `tags:` frontmatter of the Markdown files in `dir` (and below it when
+/// `recursive`) with the store, one way.
+///
+/// Only what changed on the sending side since the last sync is applied, so tags
+/// the other side picked up in the meantime (ie. from autotagging) are kept and
+/// show up on the next sync the other way. Files where both sides changed are
+/// reported as conflicts and skipped, unless `force`.
+pub fn sync_frontmatter(
+    store: &mut TagStore,
+    dir: &Path,
+    recursive: bool,
+    direction: Direction,
+    force: bool,
+) -> Result<SyncReport> {
+    let dir = dir.canonicalize().map_err(|source| Error::Canonicalize {
+        path: dir.to_path_buf(),
+        source,
+    })?;
+
+    let base_path = dir.join(FRONTMATTER_BASE);
+    let mut base = Base::load(&base_path)?;
+    let mut report = SyncReport::default();
+
+    for path in markdown_files(&dir, recursive)? {
+        let key = match path.strip_prefix(&dir) {
+            Ok(rel) => rel.to_string_lossy().to_string(),
+            Err(_) => continue,
+        };
+
+        let content = fs::read_to_string(&path).map_err(Error::file(&path))?;
+        let doc = Document::parse(&content);
+        // Rewriting an entry that wasn't understood would mangle the file
+        if doc.unparseable {
+            report.unparseable.push(path);
+            continue;
+        }
+        let frontmatter: BTreeSet<String> = doc.tags.iter().cloned().collect();
+        let stored: BTreeSet<String> = store.get_file_tags(&path)?.into_iter().collect();
+
+        // Never synced, nothing to conflict with yet
+        let last = base.files.get(&key);
+        let conflict = last.is_some_and(|last| {
+            last.frontmatter != frontmatter && last.store != stored && frontmatter != stored
+        });
+
+        if conflict && !force {
+            report.conflicts.push(Conflict {
+                path,
+                frontmatter: frontmatter.into_iter().collect(),
+                store: stored.into_iter().collect(),
+            });
+            continue;
+        }
+
+        let mut next = last.cloned().unwrap_or_default();
+
+        match direction {
+            Direction::Pull => {
+                let added: Vec<&String> = frontmatter.difference(&next.frontmatter).collect();
+                let removed: Vec<&String> = next.frontmatter.difference(&frontmatter).collect();
+                let mut changed = false;
+
+                for tag in added.iter().filter(|tag| !stored.contains(**tag)) {
+                    store.add_tags_batch(std::slice::from_ref(&path), tag)?;
+                    changed = true;
+                }
+                for tag in removed.iter().filter(|tag| stored.contains(**tag)) {
+                    store.remove_tags_batch(std::slice::from_ref(&path), tag)?;
+                    changed = true;
+                }
+
+                // Store-side changes that weren't pushed yet stay pending
+                next.store.extend(added.into_iter().cloned());
+                next.store.retain(|tag| !removed.contains(&tag));
+                next.frontmatter = frontmatter;
+
+                report.updated += changed as usize;
+            }
+            Direction::Push => {
+                let added: Vec<&String> = stored.difference(&next.store).collect();
+                let removed: Vec<&String> = next.store.difference(&stored).collect();
+
+                let mut tags: Vec<String> = doc
+                    .tags
+                    .iter()
+                    .filter(|tag| !removed.contains(tag))
+                    .cloned()
+                    .collect();
+                for tag in &added {
+                    if !tags.contains(tag) {
+                        tags.push(tag.to_string());
+                    }
+                }
+
+                if tags != doc.tags {
+                    fs::write(&path, doc.with_tags(&tags)).map_err(Error::file(&path))?;
+                    report.updated += 1;
+                }
+
+                next.frontmatter.extend(added.into_iter().cloned());
+                next.frontmatter.retain(|tag| !removed.contains(&tag));
+                next.store = stored;
+            }
+        }
+
+        base.files.insert(key, next);
+    }
+
+    base.save(&base_path)?;
+    Ok(report)
+}
+
+fn markdown_files(dir: &Path, recursive: bool) -> Result<Vec<PathBuf>> {
+    let paths: Vec<PathBuf> = if recursive {
+        walk_paths(vec![dir.to_path_buf()], true, false, 0).collect()
+    } else {
+        fs::read_dir(dir)
+            .map_err(Error::file(dir))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect()
+    };
+
+    let mut files: Vec<PathBuf> = paths
+        .into_iter()
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .is_some_and(|ext| MARKDOWN_EXTENSIONS.iter().any(|md| ext == *md))
+        })
+        .collect();
+
+    files.sort();
+    Ok(files)
+}
+
+/// The `tags:` entry of a Markdown file's YAML frontmatter. Only that entry is
+/// ever rewritten, every other line is kept byte for byte.
+#[derive(Debug)]
+struct Document<'a> {
+    lines: Vec<&'a str>,
+    /// Lines between the `---` fences
+    body: Option<Range<usize>>,
+    /// Lines of the `tags:` entry, including block list items
+    entry: Option<Range<usize>>,
+    /// Prefix of block list items (`  - `), `None` for `tags: [a, b]`
+    item_prefix: Option<&'a str>,
+    tags: Vec<String>,
+    /// The entry is there but not something [`Document::with_tags`] can replace
+    unparseable: bool,
+    newline: &'a str,
+}
+
+impl<'a> Document<'a> {
+    fn parse(content: &'a str) -> Self {
+        let lines: Vec<&str> = content.split_inclusive('\n').collect();
+        let newline = if content.contains("\r\n") {
+            "\r\n"
+        } else {
+            "\n"
+        };
+
+        let body = match lines.first() {
+            Some(first) if first.trim_end() == "---" => lines[1..]
+                .iter()
+                .position(|line| matches!(line.trim_end(), "---" | "..."))
+                .map(|end| 1..end + 1),
+            _ => None,
+        };
+
+        let mut doc = Self {
+            lines,
+            body: body.clone(),
+            entry: None,
+            item_prefix: None,
+            tags: Vec::new(),
+            unparseable: false,
+            newline,
+        };
+
+        let Some(body) = body else {
+            return doc;
+        };
+        let Some(start) = body.clone().find(|&i| doc.lines[i].starts_with("tags:")) else {
+            return doc;
+        };
+
+        let value = strip_comment(&doc.lines[start]["tags:".len()..]);
+        let mut end = start + 1;
+
+        if value.is_empty() {
+            while end < body.end {
+                let line = doc.lines[end];
+                let trimmed = line.trim_start();
+                let Some(item) = trimmed.strip_prefix('-') else {
+                    break;
+                };
+                if !item.is_empty() && !item.starts_with(char::is_whitespace) {
+                    break;
+                }
+
+                doc.item_prefix
+                    .get_or_insert(&line[..line.len() - trimmed.len()]);
+                doc.tags.extend(split_items(strip_comment(item)));
+                end += 1;
+            }
+        } else if value.starts_with('[') {
+            // Flow lists can go on for a few lines, up to the closing bracket
+            let mut flow = value.to_string();
+            while flow_items(&flow).is_none() && end < body.end {
+                flow.push(' ');
+                flow.push_str(strip_comment(doc.lines[end]));
+                end += 1;
+            }
+
+            match flow_items(&flow) {
+                Some(tags) => doc.tags = tags,
+                None => doc.unparseable = true,
+            }
+        } else {
+            doc.tags = split_items(value);
+        }
+
+        doc.entry = Some(start..end);
+        doc
+    }
+
+    /// The whole file with its tags replaced, adding the entry (and frontmatter)
+    /// when there isn't one yet.
+    fn with_tags(&self, tags: &[String]) -> String {
+        let nl = self.newline;
+        let entry = match self.item_prefix {
+            Some(prefix) if !tags.is_empty() => {
+                let items: String = tags
+                    .iter()
+                    .map(|tag| format!("{}- {}{}", prefix, quote(tag), nl))
+                    .collect();
+                format!("tags:{}{}", nl, items)
+            }
+            _ => {
+                let items: Vec<String> = tags.iter().map(|tag| quote(tag)).collect();
+                format!("tags: [{}]{}", items.join(", "), nl)
+            }
+        };
+
+        let (before, after) = match (&self.entry, &self.body) {
+            (Some(entry), _) => (entry.start, entry.end),
+            (None, Some(body)) => (body.end, body.end),
+            (None, None) => {
+                return format!("---{nl}{entry}---{nl}{}", self.lines.concat());
+            }
+        };
+
+        let mut out = self.lines[..before].concat();
+        out.push_str(&entry);
+        out.push_str(&self.lines[after..].concat());
+        out
+    }
+}
+
+/// The tags of a complete `[a, b]` flow list, `None` if it isn't closed or
+/// something other than a comment follows it.
+fn flow_items(flow: &str) -> Option<Vec<String>> {
+    let inner = flow.strip_prefix('[')?;
+    let mut quote = None;
+
+    for (i, c) in inner.char_indices() {
+        match (c, quote) {
+            (']', None) => {
+                return inner[i + 1..]
+                    .trim()
+                    .is_empty()
+                    .then(|| split_items(&inner[..i]));
+            }
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// `value # comment` without the comment, trimmed. A `#` only starts one after
+/// whitespace and outside quotes, same as YAML.
+fn strip_comment(value: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+
+    for (i, c) in value.char_indices() {
+        match (c, quote) {
+            ('#', None) if previous.is_whitespace() => return value[..i].trim(),
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            _ => {}
+        }
+        previous = c;
+    }
+
+    value.trim()
+}
+
+/// Splits a flow list or comma separated scalar, `a, "b, c"` is two tags.
+fn split_items(value: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+
+    for c in value.chars() {
+        match (c, quote) {
+            (',', None) => {
+                items.push(std::mem::take(&mut current));
+                continue;
+            }
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            _ => {}
+        }
+        current.push(c);
+    }
+    items.push(current);
+
+    items
+        .iter()
+        .map(|item| unquote(item.trim()))
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn unquote(item: &str) -> String {
+    if item.len() >= 2 && item.starts_with('"') && item.ends_with('"') {
+        item[1..item.len() - 1]
+            .replace("\\\"", "\"")
+            .replace("\\\\", "\\")
+    } else if item.len() >= 2 && item.starts_with('\'') && item.ends_with('\'') {
+        item[1..item.len() - 1].replace("''", "'")
+    } else {
+        item.to_string()
+    }
+}
+
+fn quote(tag: &str) -> String {
+    let plain = !tag.is_empty()
+        && tag.trim() == tag
+        && !tag.starts_with(['-', '?', '!', '&', '*', '%', '@', '`', '|', '>'])
+        && !tag.contains([',', '[', ']', '{', '}', ':', '#', '"', '\'']);
+
+    if plain {
+        tag.to_string()
+    } else {
+        format!("\"{}\"", tag.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn tags<'a>(doc: &'a Document) -> Vec<&'a str> {
+        doc.tags.iter().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn test_parse_styles() {
+        let flow = Document::parse("---\ntitle: a\ntags: [rust, \"a, b\"]\n---\n# hi\n");
+        assert_eq!(tags(&flow), vec!["rust", "a, b"]);
+
+        let block = Document::parse("---\ntags:\n  - rust\n  - 'it''s'\ndate: x\n---\n");
+        assert_eq!(tags(&block), vec!["rust", "it's"]);
+        assert_eq!(block.entry, Some(1..4));
+
+        let scalar = Document::parse("---\ntags: rust, cli\n---\n");
+        assert_eq!(tags(&scalar), vec!["rust", "cli"]);
+
+        assert!(Document::parse("# no frontmatter\n").tags.is_empty());
+    }
+
+    #[test]
+    fn test_parse_multiline_and_comments() {
+        let multiline = Document::parse("---\ntags: [a,\n  b, # more\n  \"c]\"]\ndate: 1\n---\n");
+        assert_eq!(tags(&multiline), vec!["a", "b", "c]"]);
+        assert_eq!(multiline.entry, Some(1..4));
+        assert_eq!(
+            multiline.with_tags(&["x".to_string()]),
+            "---\ntags: [x]\ndate: 1\n---\n"
+        );
+
+        let commented = Document::parse("---\ntags: [a] # x\n---\n");
+        assert_eq!(tags(&commented), vec!["a"]);
+        assert!(!commented.unparseable);
+
+        let block = Document::parse("---\ntags:\n  - a # why\n  - \"#b\"\n---\n");
+        assert_eq!(tags(&block), vec!["a", "#b"]);
+
+        // Never closed, or more than a comment after it
+        assert!(Document::parse("---\ntags: [a,\n  b\n---\n").unparseable);
+        assert!(Document::parse("---\ntags: [a] b\n---\n").unparseable);
+    }
+
+    #[test]
+    fn test_with_tags_keeps_the_rest() {
+        let new = vec!["rust".to_string(), "a: b".to_string()];
+
+        let doc = Document::parse("---\ntitle: a\ntags: [x]\ndate: 1\n---\nbody\n");
+        assert_eq!(
+            doc.with_tags(&new),
+            "---\ntitle: a\ntags: [rust, \"a: b\"]\ndate: 1\n---\nbody\n"
+        );
+
+        let doc = Document::parse("---\ntags:\n  - x\n---\nbody");
+        assert_eq!(
+            doc.with_tags(&new),
+            "---\ntags:\n  - rust\n  - \"a: b\"\n---\nbody"
+        );
+
+        let doc = Document::parse("---\ntitle: a\n---\nbody\n");
+        assert_eq!(
+            doc.with_tags(&new[..1]),
+            "---\ntitle: a\ntags: [rust]\n---\nbody\n"
+        );
+
+        let doc = Document::parse("body\n");
+        assert_eq!(doc.with_tags(&new[..1]), "---\ntags: [rust]\n---\nbody\n");
+    }
+
+    #[test]
+    fn test_sync_skips_unparseable() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let note = temp_dir.path().join("note.md");
+        let content = "---\ntags: [idea,\n  draft\n---\ntext\n";
+        fs::write(&note, content)?;
+
+        let mut store = TagStore::in_memory();
+        store.add_tags_batch(std::slice::from_ref(&note), "file")?;
+
+        let report = sync_frontmatter(&mut store, temp_dir.path(), false, Direction::Push, false)?;
+        assert_eq!(report.unparseable, vec![note.canonicalize()?]);
+        assert_eq!(fs::read_to_string(&note)?, content);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_and_conflicts() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let dir = temp_dir.path();
+        let note = dir.join("note.md");
+        fs::write(&note, "---\ntags: [idea]\n---\ntext\n")?;
+
+        let mut store = TagStore::in_memory();
+        store.add_tags_batch(std::slice::from_ref(&note), "file")?;
+
+        let report = sync_frontmatter(&mut store, dir, false, Direction::Pull, false)?;
+        assert_eq!(report.updated, 1);
+        assert_eq!(store.get_file_tags(&note)?, vec!["file", "idea"]);
+
+        // `file` was never pushed, it's still pending
+        let report = sync_frontmatter(&mut store, dir, false, Direction::Push, false)?;
+        assert_eq!(report.updated, 1);
+        assert_eq!(
+            fs::read_to_string(&note)?,
+            "---\ntags: [idea, file]\n---\ntext\n"
+        );
+
+        // Both sides change
+        fs::write(&note, "---\ntags: [idea, file, draft]\n---\ntext\n")?;
+        store.remove_tags_batch(std::slice::from_ref(&note), "idea")?;
+
+        let report = sync_frontmatter(&mut store, dir, false, Direction::Pull, false)?;
+        assert_eq!(report.updated, 0);
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(report.conflicts[0].store, vec!["file"]);
+
+        let report = sync_frontmatter(&mut store, dir, false, Direction::Pull, true)?;
+        assert_eq!(report.updated, 1);
+        assert_eq!(store.get_file_tags(&note)?, vec!["draft", "file"]);
+
+        Ok(())
+    }
+}
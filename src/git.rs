@@ -0,0 +1,329 @@
+//! Git state tags, read straight from `.git` rather than by running git.
+
+use gix::{refs::FullNameRef, remote::Direction, status::UntrackedFiles, url::Scheme, Repository};
+use std::{
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Tags for the repository at `dir`, empty if it isn't one:
+/// `git:branch=<name>` or `git:detached`, `git:ahead` of the upstream branch,
+/// `git:dirty`, `git:remote-host=<host>` and `git:stale` if the last commit is
+/// older than `stale_after`.
+pub fn git_tags(dir: &Path, stale_after: Duration) -> Vec<String> {
+    // Only the root of a repo, not every directory in it
+    if !dir.join(".git").exists() {
+        return Vec::new();
+    }
+    let Ok(repo) = gix::open(dir) else {
+        return Vec::new();
+    };
+
+    let mut tags = Vec::new();
+
+    if let Ok(head) = repo.head() {
+        match head.referent_name() {
+            _ if head.is_detached() => tags.push("git:detached".to_string()),
+            Some(name) => {
+                if is_ahead(&repo, name) == Some(true) {
+                    tags.push("git:ahead".to_string());
+                }
+                tags.push(format!("git:branch={}", name.shorten()));
+            }
+            None => {}
+        }
+    }
+
+    if let Some(host) = remote_host(&repo) {
+        tags.push(format!("git:remote-host={}", host));
+    }
+
+    // Unborn branches have no commit to be old
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64);
+    let committed = repo
+        .head_commit()
+        .ok()
+        .and_then(|commit| commit.time().ok());
+    if committed.is_some_and(|time| now - time.seconds > stale_after.as_secs() as i64) {
+        tags.push("git:stale".to_string());
+    }
+
+    if is_dirty(&repo) == Some(true) {
+        tags.push("git:dirty".to_string());
+    }
+
+    tags
+}
+
+/// Whether `branch` has commits its upstream doesn't, ie. unpushed work.
+fn is_ahead(repo: &Repository, branch: &FullNameRef) -> Option<bool> {
+    let upstream = repo
+        .branch_remote_tracking_ref_name(branch, Direction::Fetch)?
+        .ok()?;
+    let upstream = repo
+        .find_reference(upstream.as_ref())
+        .ok()?
+        .peel_to_id()
+        .ok()?;
+    let local = repo.find_reference(branch).ok()?.peel_to_id().ok()?;
+
+    let mut unpushed = repo.rev_walk([local]).with_hidden([upstream]).all().ok()?;
+    Some(unpushed.next().transpose().ok()?.is_some())
+}
+
+/// Host of the branch's remote, or `origin`, or whatever remote there is.
+fn remote_host(repo: &Repository) -> Option<String> {
+    let remote = match repo.find_default_remote(Direction::Fetch) {
+        Some(remote) => remote.ok()?,
+        None => {
+            let name = repo.remote_names().into_iter().next()?;
+            repo.find_remote(name.as_ref()).ok()?
+        }
+    };
+
+    let url = remote.url(Direction::Fetch)?;
+    if url.scheme == Scheme::File {
+        return None;
+    }
+    url.host().map(str::to_lowercase)
+}
+
+/// Like `git status` being non-empty: conflicts, staged or unstaged changes,
+/// or untracked files that aren't ignored. Submodules aren't looked into.
+fn is_dirty(repo: &Repository) -> Option<bool> {
+    let mut changes = repo
+        .status(gix::progress::Discard)
+        .ok()?
+        .untracked_files(UntrackedFiles::Collapsed)
+        .index_worktree_submodules(None)
+        .into_iter(Vec::new())
+        .ok()?;
+
+    Some(changes.next().transpose().ok()?.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, process::Command};
+    use tempfile::TempDir;
+
+    /// The tests build repos with the git CLI, they're skipped without it.
+    fn has_git() -> bool {
+        Command::new("git")
+            .arg("--version")
+            .output()
+            .is_ok_and(|output| output.status.success())
+    }
+
+    fn git(dir: &Path, args: &[&str]) {
+        git_with(dir, &[], args)
+    }
+
+    fn git_with(dir: &Path, env: &[(&str, &str)], args: &[&str]) {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_AUTHOR_NAME", "stag")
+            .env("GIT_AUTHOR_EMAIL", "stag@example.com")
+            .env("GIT_COMMITTER_NAME", "stag")
+            .env("GIT_COMMITTER_EMAIL", "stag@example.com")
+            .envs(env.iter().copied())
+            .output()
+            .expect("git is installed");
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    fn tags(dir: &Path) -> Vec<String> {
+        let mut tags = git_tags(dir, Duration::from_secs(30 * 86400));
+        tags.sort();
+        tags
+    }
+
+    #[test]
+    fn test_dirty_and_remote() -> std::io::Result<()> {
+        if !has_git() {
+            return Ok(());
+        }
+        let temp_dir = TempDir::new()?;
+        let repo = temp_dir.path();
+        git(repo, &["init", "-q", "-b", "main"]);
+        fs::write(repo.join("a.txt"), "a")?;
+        fs::write(repo.join(".gitignore"), "*.log\n")?;
+        git(repo, &["add", "."]);
+        git(repo, &["commit", "-q", "-m", "init"]);
+
+        assert_eq!(tags(repo), vec!["git:branch=main"]);
+
+        git(
+            repo,
+            &["remote", "add", "origin", "git@github.com:lnus/stag.git"],
+        );
+        assert!(tags(repo).contains(&"git:remote-host=github.com".to_string()));
+
+        // Ignored files don't count, untracked ones do
+        fs::write(repo.join("debug.log"), "")?;
+        assert!(!tags(repo).contains(&"git:dirty".to_string()));
+        fs::write(repo.join("b.txt"), "b")?;
+        assert!(tags(repo).contains(&"git:dirty".to_string()));
+        fs::remove_file(repo.join("b.txt"))?;
+
+        fs::write(repo.join("a.txt"), "changed")?;
+        assert!(tags(repo).contains(&"git:dirty".to_string()));
+
+        // Same content again, only the mtime differs from the index
+        fs::write(repo.join("a.txt"), "a")?;
+        assert!(!tags(repo).contains(&"git:dirty".to_string()));
+
+        // Sparse checkouts leave files out on purpose
+        git(repo, &["update-index", "--skip-worktree", "a.txt"]);
+        fs::remove_file(repo.join("a.txt"))?;
+        assert!(!tags(repo).contains(&"git:dirty".to_string()));
+
+        fs::write(repo.join("c.txt"), "c")?;
+        git(repo, &["add", "c.txt"]);
+        assert!(tags(repo).contains(&"git:dirty".to_string()));
+
+        git(repo, &["update-index", "--split-index"]);
+        assert!(tags(repo).contains(&"git:dirty".to_string()));
+        git(repo, &["commit", "-q", "-m", "c"]);
+        assert!(!tags(repo).contains(&"git:dirty".to_string()));
+
+        // Excludes from outside the repo count too
+        let config_dir = TempDir::new()?;
+        let excludes = config_dir.path().join("excludes");
+        fs::write(&excludes, "*.tmp\n")?;
+        fs::write(repo.join("scratch.tmp"), "")?;
+        assert!(tags(repo).contains(&"git:dirty".to_string()));
+        git(
+            repo,
+            &["config", "core.excludesFile", &excludes.to_string_lossy()],
+        );
+        assert!(!tags(repo).contains(&"git:dirty".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ahead_stale_and_detached() -> std::io::Result<()> {
+        if !has_git() {
+            return Ok(());
+        }
+        let temp_dir = TempDir::new()?;
+        let repo = temp_dir.path();
+        let old = [("GIT_COMMITTER_DATE", "2020-01-01T00:00:00Z")];
+
+        git(repo, &["init", "-q", "-b", "main"]);
+        fs::write(repo.join("a.txt"), "first\n".repeat(100))?;
+        git(repo, &["add", "."]);
+        git_with(repo, &old, &["commit", "-q", "-m", "first"]);
+
+        git(repo, &["remote", "add", "origin", "/srv/git/stag.git"]);
+        git(repo, &["update-ref", "refs/remotes/origin/main", "HEAD"]);
+        git(repo, &["config", "branch.main.remote", "origin"]);
+        git(repo, &["config", "branch.main.merge", "refs/heads/main"]);
+        assert_eq!(tags(repo), vec!["git:branch=main", "git:stale"]);
+
+        fs::write(repo.join("a.txt"), "second\n".repeat(100))?;
+        git(repo, &["commit", "-q", "-am", "second"]);
+        assert_eq!(tags(repo), vec!["git:ahead", "git:branch=main"]);
+
+        // Same answers from packs and packed-refs
+        git(repo, &["gc", "-q", "--prune=now"]);
+        assert!(!repo.join(".git/refs/heads/main").exists());
+        assert_eq!(tags(repo), vec!["git:ahead", "git:branch=main"]);
+
+        // Behind isn't ahead
+        git(repo, &["update-ref", "refs/remotes/origin/main", "HEAD"]);
+        git(repo, &["reset", "-q", "--hard", "HEAD~1"]);
+        assert_eq!(tags(repo), vec!["git:branch=main", "git:stale"]);
+
+        git(repo, &["checkout", "-q", "--detach"]);
+        assert_eq!(tags(repo), vec!["git:detached", "git:stale"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_not_a_repo() -> std::io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        assert!(git_tags(temp_dir.path(), Duration::ZERO).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_packed_deltas() -> std::io::Result<()> {
+        if !has_git() {
+            return Ok(());
+        }
+        let temp_dir = TempDir::new()?;
+        let repo = temp_dir.path();
+        git(repo, &["init", "-q", "-b", "main"]);
+
+        // Lots of small edits to one big file, which gc stores as delta chains
+        let mut lines: Vec<String> = (0..500).map(|i| format!("line {}", i)).collect();
+        for i in 0..30 {
+            lines[i * 7] = format!("edit {}", i);
+            fs::write(repo.join("big.txt"), lines.join("\n"))?;
+            git(repo, &["add", "."]);
+            git(repo, &["commit", "-q", "-m", &format!("edit {}", i)]);
+            if i == 20 {
+                git(repo, &["update-ref", "refs/remotes/origin/main", "HEAD"]);
+            }
+        }
+        git(repo, &["remote", "add", "origin", "/srv/git/stag.git"]);
+        git(repo, &["config", "branch.main.remote", "origin"]);
+        git(repo, &["config", "branch.main.merge", "refs/heads/main"]);
+        git(repo, &["update-index", "--index-version", "4"]);
+        git(repo, &["gc", "-q", "--aggressive", "--prune=now"]);
+
+        let packs = fs::read_dir(repo.join(".git/objects/pack"))?.count();
+        assert!(packs > 0 && !repo.join(".git/refs/heads/main").exists());
+        assert_eq!(tags(repo), vec!["git:ahead", "git:branch=main"]);
+
+        fs::write(repo.join("big.txt"), "rewritten")?;
+        assert!(tags(repo).contains(&"git:dirty".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_remote_hosts() -> std::io::Result<()> {
+        if !has_git() {
+            return Ok(());
+        }
+        let temp_dir = TempDir::new()?;
+        let repo = temp_dir.path();
+        git(repo, &["init", "-q", "-b", "main"]);
+
+        let host = |url: &str| -> Option<String> {
+            git(repo, &["remote", "set-url", "origin", url]);
+            tags(repo)
+                .into_iter()
+                .find_map(|tag| Some(tag.strip_prefix("git:remote-host=")?.to_string()))
+        };
+        git(repo, &["remote", "add", "origin", "/srv/git/stag.git"]);
+
+        assert_eq!(
+            host("https://github.com/lnus/stag"),
+            Some("github.com".into())
+        );
+        assert_eq!(host("git@GitLab.com:a/b.git"), Some("gitlab.com".into()));
+        assert_eq!(
+            host("ssh://git@git.example.org:2222/a/b"),
+            Some("git.example.org".into())
+        );
+        assert_eq!(host("/srv/git/stag.git"), None);
+        assert_eq!(host("file:///srv/git/stag.git"), None);
+
+        Ok(())
+    }
+}
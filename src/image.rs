@@ -0,0 +1,473 @@
+//! Photo tags from JPEG and PNG headers and the EXIF data in them.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, Read},
+    path::Path,
+};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// PNG chunks bigger than this are skipped rather than read into memory.
+const MAX_CHUNK_LEN: usize = 1 << 20;
+
+// IFD0
+const MODEL: u16 = 0x0110;
+const ORIENTATION: u16 = 0x0112;
+const DATE_TIME: u16 = 0x0132;
+const EXIF_IFD: u16 = 0x8769;
+const GPS_IFD: u16 = 0x8825;
+// Exif IFD
+const DATE_TIME_ORIGINAL: u16 = 0x9003;
+const PIXEL_WIDTH: u16 = 0xa002;
+const PIXEL_HEIGHT: u16 = 0xa003;
+// GPS IFD
+const GPS_LATITUDE: u16 = 0x0002;
+
+/// Shortest long and short side for each resolution tag, biggest first.
+const RESOLUTIONS: &[(u32, u32, &str)] = &[
+    (7680, 4320, "8k"),
+    (3840, 2160, "4k"),
+    (1920, 1080, "1080p"),
+    (1280, 720, "720p"),
+];
+
+#[derive(Debug, Default, PartialEq)]
+struct Exif {
+    model: Option<String>,
+    /// `YYYY:MM:DD HH:MM:SS`, when it was taken if known
+    date: Option<String>,
+    orientation: Option<u32>,
+    width: Option<u32>,
+    height: Option<u32>,
+    gps: bool,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct ImageInfo {
+    /// From the image header, as stored ie. before EXIF rotation
+    width: Option<u32>,
+    height: Option<u32>,
+    exif: Exif,
+}
+
+/// Tags for a JPEG or PNG: `camera:<model>`, `year:<year>`,
+/// `orientation:portrait|landscape|square`, `resolution:<8k|4k|1080p|720p>` and
+/// `has-gps`. Empty for anything else.
+pub fn image_tags(path: &Path) -> Vec<String> {
+    read_info(path).map(|info| info.tags()).unwrap_or_default()
+}
+
+fn read_info(path: &Path) -> io::Result<ImageInfo> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut signature = [0; 8];
+    reader.read_exact(&mut signature)?;
+
+    let mut info = ImageInfo::default();
+    let read = if signature == PNG_SIGNATURE {
+        png(&mut reader, &mut info)
+    } else if signature.starts_with(b"\xff\xd8") {
+        // The first segment's marker is already read
+        jpeg(&mut (&signature[2..]).chain(reader), &mut info)
+    } else {
+        Ok(())
+    };
+
+    // A truncated file still has whatever came before the end
+    match read {
+        Err(err) if err.kind() != io::ErrorKind::UnexpectedEof => Err(err),
+        _ => Ok(info),
+    }
+}
+
+/// Walks the segments up to the frame header, EXIF is in APP1 before it.
+fn jpeg(reader: &mut impl Read, info: &mut ImageInfo) -> io::Result<()> {
+    loop {
+        let mut marker = [0; 2];
+        reader.read_exact(&mut marker)?;
+        if marker[0] != 0xff {
+            break;
+        }
+        // Any number of 0xff can pad a marker
+        let mut kind = marker[1];
+        while kind == 0xff {
+            reader.read_exact(std::slice::from_mut(&mut kind))?;
+        }
+
+        match kind {
+            // Markers without a length
+            0x01 | 0xd0..=0xd8 => continue,
+            // End of image or start of the compressed data
+            0xd9 | 0xda => break,
+            _ => {}
+        }
+
+        let mut len = [0; 2];
+        reader.read_exact(&mut len)?;
+        let len = (u16::from_be_bytes(len) as usize).saturating_sub(2);
+
+        let is_frame = matches!(kind, 0xc0..=0xcf) && !matches!(kind, 0xc4 | 0xc8 | 0xcc);
+        if kind != 0xe1 && !is_frame {
+            io::copy(&mut reader.take(len as u64), &mut io::sink())?;
+            continue;
+        }
+
+        let mut data = vec![0; len];
+        reader.read_exact(&mut data)?;
+
+        if is_frame {
+            // Precision, then height and width
+            info.height = be16(&data, 1).map(u32::from);
+            info.width = be16(&data, 3).map(u32::from);
+            break;
+        }
+        if let Some(tiff) = data.strip_prefix(b"Exif\0\0") {
+            info.exif = parse_exif(tiff).unwrap_or_default();
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads IHDR and an `eXIf` chunk if there's one before the image data.
+fn png(reader: &mut impl Read, info: &mut ImageInfo) -> io::Result<()> {
+    loop {
+        let mut header = [0; 8];
+        reader.read_exact(&mut header)?;
+        let len = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+
+        match &header[4..] {
+            b"IDAT" | b"IEND" => break,
+            b"IHDR" | b"eXIf" if len <= MAX_CHUNK_LEN => {
+                let mut data = vec![0; len];
+                reader.read_exact(&mut data)?;
+
+                match &header[4..] {
+                    b"IHDR" => {
+                        info.width = be32(&data, 0);
+                        info.height = be32(&data, 4);
+                    }
+                    _ => info.exif = parse_exif(&data).unwrap_or_default(),
+                }
+                // CRC
+                io::copy(&mut reader.take(4), &mut io::sink())?;
+            }
+            _ => {
+                io::copy(&mut reader.take(len as u64 + 4), &mut io::sink())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A TIFF structure, which is what EXIF data is.
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    /// Where the value, or its offset when it doesn't fit in 4 bytes, is
+    at: usize,
+}
+
+fn parse_exif(data: &[u8]) -> Option<Exif> {
+    let big_endian = match data.get(..4)? {
+        b"II*\0" => false,
+        b"MM\0*" => true,
+        _ => return None,
+    };
+    let tiff = Tiff { data, big_endian };
+    let mut exif = Exif::default();
+
+    let ifd0 = tiff.u32(4)? as usize;
+    for entry in tiff.entries(ifd0) {
+        match entry.tag {
+            MODEL => exif.model = tiff.ascii(&entry),
+            ORIENTATION => exif.orientation = tiff.uint(&entry),
+            DATE_TIME if exif.date.is_none() => exif.date = tiff.ascii(&entry),
+            EXIF_IFD => {
+                let Some(offset) = tiff.uint(&entry) else {
+                    continue;
+                };
+                for entry in tiff.entries(offset as usize) {
+                    match entry.tag {
+                        DATE_TIME_ORIGINAL => exif.date = tiff.ascii(&entry).or(exif.date),
+                        PIXEL_WIDTH => exif.width = tiff.uint(&entry),
+                        PIXEL_HEIGHT => exif.height = tiff.uint(&entry),
+                        _ => {}
+                    }
+                }
+            }
+            // Some phones write the GPS block even with location off, only
+            // count it with an actual position
+            GPS_IFD => {
+                exif.gps = tiff.uint(&entry).is_some_and(|offset| {
+                    tiff.entries(offset as usize)
+                        .any(|entry| entry.tag == GPS_LATITUDE)
+                })
+            }
+            _ => {}
+        }
+    }
+
+    Some(exif)
+}
+
+impl Tiff<'_> {
+    fn u16(&self, at: usize) -> Option<u16> {
+        let bytes = self.data.get(at..at + 2)?.try_into().ok()?;
+        Some(match self.big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        })
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        let bytes = self.data.get(at..at + 4)?.try_into().ok()?;
+        Some(match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    }
+
+    fn entries(&self, offset: usize) -> impl Iterator<Item = Entry> + '_ {
+        let count = self.u16(offset).unwrap_or(0) as usize;
+        (0..count).map_while(move |i| {
+            let at = offset + 2 + i * 12;
+            Some(Entry {
+                tag: self.u16(at)?,
+                kind: self.u16(at + 2)?,
+                count: self.u32(at + 4)?,
+                at: at + 8,
+            })
+        })
+    }
+
+    /// SHORT or LONG values.
+    fn uint(&self, entry: &Entry) -> Option<u32> {
+        match entry.kind {
+            3 => self.u16(entry.at).map(u32::from),
+            4 => self.u32(entry.at),
+            _ => None,
+        }
+    }
+
+    fn ascii(&self, entry: &Entry) -> Option<String> {
+        if entry.kind != 2 {
+            return None;
+        }
+        let len = entry.count as usize;
+        let start = match len <= 4 {
+            true => entry.at,
+            false => self.u32(entry.at)? as usize,
+        };
+        let bytes = self.data.get(start..start.checked_add(len)?)?;
+        let text = String::from_utf8_lossy(bytes);
+        let text = text.trim_end_matches('\0').trim();
+        (!text.is_empty()).then(|| text.to_string())
+    }
+}
+
+impl ImageInfo {
+    fn tags(&self) -> Vec<String> {
+        let mut tags = Vec::new();
+
+        if let Some(model) = &self.exif.model {
+            let model = model.to_lowercase();
+            let words: Vec<&str> = model.split_whitespace().collect();
+            tags.push(format!("camera:{}", words.join("-")));
+        }
+
+        if let Some(year) = self.exif.date.as_deref().and_then(|date| date.get(..4)) {
+            if year.bytes().all(|b| b.is_ascii_digit()) && year != "0000" {
+                tags.push(format!("year:{}", year));
+            }
+        }
+
+        if let Some((width, height)) = self.dimensions() {
+            let orientation = match width.cmp(&height) {
+                std::cmp::Ordering::Less => "portrait",
+                std::cmp::Ordering::Equal => "square",
+                std::cmp::Ordering::Greater => "landscape",
+            };
+            tags.push(format!("orientation:{}", orientation));
+
+            let (long, short) = (width.max(height), width.min(height));
+            if let Some((_, _, resolution)) = RESOLUTIONS
+                .iter()
+                .find(|(min_long, min_short, _)| long >= *min_long && short >= *min_short)
+            {
+                tags.push(format!("resolution:{}", resolution));
+            }
+        }
+
+        if self.exif.gps {
+            tags.push("has-gps".to_string());
+        }
+
+        tags
+    }
+
+    /// Width and height as displayed, EXIF orientations 5-8 are rotated by 90°.
+    fn dimensions(&self) -> Option<(u32, u32)> {
+        let width = self.width.or(self.exif.width).filter(|&w| w > 0)?;
+        let height = self.height.or(self.exif.height).filter(|&h| h > 0)?;
+
+        match self.exif.orientation {
+            Some(5..=8) => Some((height, width)),
+            _ => Some((width, height)),
+        }
+    }
+}
+
+fn be32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn be16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// Little endian EXIF with a model, date, orientation and a GPS position.
+    fn exif(model: &str, date: &str, orientation: u16) -> Vec<u8> {
+        let model = format!("{}\0", model);
+        let date = format!("{}\0", date);
+        let exif_ifd = 8 + 2 + 4 * 12 + 4;
+        let gps_ifd = exif_ifd + 2 + 12 + 4;
+        let strings = gps_ifd + 2 + 12 + 4;
+
+        let mut out = b"II*\0".to_vec();
+        out.extend(8u32.to_le_bytes());
+        let entry = |out: &mut Vec<u8>, tag: u16, kind: u16, count: usize, value: u32| {
+            out.extend(tag.to_le_bytes());
+            out.extend(kind.to_le_bytes());
+            out.extend((count as u32).to_le_bytes());
+            out.extend(value.to_le_bytes());
+        };
+
+        out.extend(4u16.to_le_bytes());
+        entry(&mut out, MODEL, 2, model.len(), strings);
+        entry(&mut out, ORIENTATION, 3, 1, orientation.into());
+        entry(&mut out, EXIF_IFD, 4, 1, exif_ifd);
+        entry(&mut out, GPS_IFD, 4, 1, gps_ifd);
+        out.extend(0u32.to_le_bytes());
+
+        out.extend(1u16.to_le_bytes());
+        let date_at = strings + model.len() as u32;
+        entry(&mut out, DATE_TIME_ORIGINAL, 2, date.len(), date_at);
+        out.extend(0u32.to_le_bytes());
+
+        out.extend(1u16.to_le_bytes());
+        entry(&mut out, GPS_LATITUDE, 5, 3, 0);
+        out.extend(0u32.to_le_bytes());
+
+        out.extend(model.as_bytes());
+        out.extend(date.as_bytes());
+        out
+    }
+
+    fn jpeg(exif: &[u8], width: u16, height: u16) -> Vec<u8> {
+        let mut out = vec![0xff, 0xd8, 0xff, 0xe0, 0, 4, 0, 0];
+        out.extend([0xff, 0xe1]);
+        out.extend((exif.len() as u16 + 8).to_be_bytes());
+        out.extend(b"Exif\0\0");
+        out.extend(exif);
+        out.extend([0xff, 0xc0, 0, 11, 8]);
+        out.extend(height.to_be_bytes());
+        out.extend(width.to_be_bytes());
+        out.extend([1, 1, 0x11, 0]);
+        out.extend([0xff, 0xda, 0, 2, 0xff, 0xd9]);
+        out
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut out = PNG_SIGNATURE.to_vec();
+        out.extend(13u32.to_be_bytes());
+        out.extend(b"IHDR");
+        out.extend(width.to_be_bytes());
+        out.extend(height.to_be_bytes());
+        out.extend([8, 2, 0, 0, 0, 0, 0, 0, 0]);
+        out.extend(0u32.to_be_bytes());
+        out.extend(b"IEND");
+        out
+    }
+
+    #[test]
+    fn test_jpeg_tags() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let photo = temp_dir.path().join("IMG_0001.JPG");
+
+        // Rotated, so stored landscape but shown portrait
+        let data = exif("Canon EOS  R5", "2024:06:01 12:00:00", 6);
+        fs::write(&photo, jpeg(&data, 6000, 4000))?;
+        assert_eq!(
+            image_tags(&photo),
+            vec![
+                "camera:canon-eos-r5",
+                "year:2024",
+                "orientation:portrait",
+                "resolution:4k",
+                "has-gps"
+            ]
+        );
+
+        // Cut off before the frame header, the EXIF is still there
+        let full = jpeg(&data, 6000, 4000);
+        fs::write(&photo, &full[..18 + data.len()])?;
+        assert_eq!(
+            image_tags(&photo),
+            vec!["camera:canon-eos-r5", "year:2024", "has-gps"]
+        );
+
+        fs::write(&photo, jpeg(b"not exif", 1920, 1080))?;
+        assert_eq!(
+            image_tags(&photo),
+            vec!["orientation:landscape", "resolution:1080p"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_png_tags() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let image = temp_dir.path().join("icon.png");
+
+        fs::write(&image, png(512, 512))?;
+        assert_eq!(image_tags(&image), vec!["orientation:square"]);
+
+        fs::write(&image, b"just text")?;
+        assert!(image_tags(&image).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_big_endian_exif() {
+        // Model and orientation only, stored inline and big endian
+        let mut data = b"MM\0*\0\0\0\x08\0\x02".to_vec();
+        data.extend([0x01, 0x10, 0, 2, 0, 0, 0, 3, b'X', b'1', 0, 0]);
+        data.extend([0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 8, 0, 0]);
+
+        assert_eq!(
+            parse_exif(&data),
+            Some(Exif {
+                model: Some("X1".to_string()),
+                orientation: Some(8),
+                ..Default::default()
+            })
+        );
+        assert_eq!(parse_exif(b"nope"), None);
+    }
+}
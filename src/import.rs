@@ -0,0 +1,94 @@
+use rusqlite::{Connection, OpenFlags};
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use crate::{Error, Result, TagStore};
+
+mod queries {
+    pub const TMSU_FILE_TAGS: &str = include_str!("./sql/queries/tmsu_file_tags.sql");
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub missing: BTreeSet<PathBuf>,
+}
+
+/// Imports every file/tag pair from a TMSU database.
+///
+/// TMSU tag values have no equivalent in stag, so `year=2017` is imported as
+/// the tag `year=2017`, which is also how TMSU itself spells it in queries.
+/// Files that no longer exist can't be stored and end up in `missing`.
+///
+/// Local TMSU databases store paths relative to their root, `root` is that
+/// directory. Without it, it's only known for the usual `<root>/.tmsu/db` layout.
+/// `imported` only counts pairs that weren't in the store yet.
+pub fn import_tmsu(store: &mut TagStore, db: &Path, root: Option<&Path>) -> Result<ImportReport> {
+    let conn = Connection::open_with_flags(db, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+    let root = root.map(Path::to_path_buf).or_else(|| local_root(db));
+
+    let mut stmt = conn
+        .prepare(queries::TMSU_FILE_TAGS)
+        .map_err(|err| match err {
+            rusqlite::Error::SqliteFailure(_, Some(ref msg))
+                if msg.starts_with("no such table") =>
+            {
+                Error::NotTmsu(db.to_path_buf())
+            }
+            err => err.into(),
+        })?;
+
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, Option<String>>(3)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut report = ImportReport::default();
+    let mut tag_map: HashMap<String, Vec<PathBuf>> = HashMap::new();
+
+    for (directory, name, tag, value) in rows {
+        let directory = PathBuf::from(directory);
+        let path = match &root {
+            _ if directory.is_absolute() => directory.join(name),
+            Some(root) => root.join(directory).join(name),
+            None => return Err(Error::TmsuRootUnknown(db.to_path_buf())),
+        };
+        if !path.exists() {
+            report.missing.insert(path);
+            continue;
+        }
+
+        let tag = match value {
+            Some(value) if !value.is_empty() => format!("{}={}", tag, value),
+            _ => tag,
+        };
+
+        tag_map.entry(tag).or_default().push(path);
+    }
+
+    for (tag, paths) in tag_map {
+        report.imported += store.add_tags_batch(&paths, &tag)?.changed;
+    }
+
+    Ok(report)
+}
+
+/// `<root>` for a database at `<root>/.tmsu/db`.
+fn local_root(db: &Path) -> Option<PathBuf> {
+    let db = db.canonicalize().ok()?;
+    let tmsu_dir = db.parent()?;
+
+    match tmsu_dir.file_name()? == ".tmsu" {
+        true => tmsu_dir.parent().map(Path::to_path_buf),
+        false => None,
+    }
+}
@@ -0,0 +1,35 @@
+//! stag, a (s)tag management library.
+//!
+//! [`TagStore`] is the entry point, everything else builds on top of it.
+//! The `stag` binary is a thin CLI over this crate.
+
+pub mod autotag;
+pub mod backend;
+pub mod config;
+pub mod error;
+pub mod federated;
+pub mod frontmatter;
+pub mod git;
+pub mod image;
+pub mod import;
+pub mod progress;
+pub mod project;
+pub mod query;
+pub mod rules;
+pub mod sidecar;
+pub mod sniff;
+pub mod stores;
+pub mod tagstore;
+pub mod view;
+pub mod walk;
+pub mod xattrs;
+
+pub use backend::Relocation;
+pub use config::Config;
+pub use error::{Error, Result};
+pub use federated::Federation;
+pub use progress::Progress;
+pub use query::Query;
+pub use rules::RuleSet;
+pub use tagstore::{Changes, TagChange, TagStore};
+pub use walk::{collect_paths, walk_map, walk_paths};
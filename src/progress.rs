@@ -0,0 +1,55 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Live counters for long operations, cheap to clone and safe to read from
+/// another thread while walker threads update them.
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    counters: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    walked: AtomicUsize,
+    skipped: AtomicUsize,
+    processed: AtomicUsize,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Entries the walker produced, including skipped ones.
+    pub fn walked(&self) -> usize {
+        self.counters.walked.load(Ordering::Relaxed)
+    }
+
+    /// Entries that couldn't be read (permissions, vanished mid walk, ...).
+    pub fn skipped(&self) -> usize {
+        self.counters.skipped.load(Ordering::Relaxed)
+    }
+
+    /// Paths that made it through to the store.
+    pub fn processed(&self) -> usize {
+        self.counters.processed.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn walk(&self) {
+        self.counters.walked.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn skip(&self) {
+        self.counters.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts every item as processed as it's pulled out of `iter`.
+    pub fn track<I: Iterator>(&self, iter: I) -> impl Iterator<Item = I::Item> {
+        let progress = self.clone();
+        iter.inspect(move |_| {
+            progress.counters.processed.fetch_add(1, Ordering::Relaxed);
+        })
+    }
+}
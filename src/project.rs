@@ -0,0 +1,326 @@
+use std::{fs, path::Path};
+
+/// Marker files and the tags a directory containing them gets.
+const MARKERS: &[(&str, &[&str])] = &[
+    ("Cargo.toml", &["rust"]),
+    ("package.json", &["node"]),
+    ("tsconfig.json", &["typescript"]),
+    ("deno.json", &["deno"]),
+    ("pyproject.toml", &["python"]),
+    ("setup.py", &["python"]),
+    ("requirements.txt", &["python"]),
+    ("Pipfile", &["python"]),
+    ("go.mod", &["go"]),
+    ("CMakeLists.txt", &["cmake"]),
+    ("meson.build", &["meson"]),
+    ("Makefile", &["make"]),
+    ("pom.xml", &["java", "maven"]),
+    ("build.gradle", &["gradle"]),
+    ("build.gradle.kts", &["gradle", "kotlin"]),
+    ("Gemfile", &["ruby"]),
+    ("composer.json", &["php"]),
+    ("mix.exs", &["elixir"]),
+    ("pubspec.yaml", &["dart"]),
+    ("Package.swift", &["swift"]),
+    ("build.zig", &["zig"]),
+    ("dune-project", &["ocaml"]),
+    ("stack.yaml", &["haskell"]),
+    ("flake.nix", &["nix"]),
+    ("Dockerfile", &["docker"]),
+];
+
+/// Manifest, ecosystem and how to get the dependency names out of it.
+type Manifest = (&'static str, &'static str, fn(&str) -> Vec<String>);
+
+const MANIFESTS: &[Manifest] = &[
+    ("Cargo.toml", "cargo", cargo_deps),
+    ("package.json", "npm", npm_deps),
+    ("requirements.txt", "pypi", requirements_deps),
+    ("go.mod", "go", go_deps),
+];
+
+/// Files that only exist at the root of a workspace/monorepo.
+const WORKSPACE_MARKERS: &[(&str, &str)] = &[
+    ("pnpm-workspace.yaml", "pnpm"),
+    ("lerna.json", "lerna"),
+    ("go.work", "go"),
+    ("nx.json", "nx"),
+    ("turbo.json", "turbo"),
+];
+
+/// Project tags for a directory from the marker files in it: languages and build
+/// tools, `project` if there was any, and `workspace` + `workspace:<kind>` for
+/// workspace roots.
+pub fn project_tags(dir: &Path) -> Vec<String> {
+    let mut tags: Vec<String> = MARKERS
+        .iter()
+        .filter(|(marker, _)| dir.join(marker).is_file())
+        .flat_map(|(_, tags)| tags.iter().map(|tag| tag.to_string()))
+        .collect();
+
+    if !tags.is_empty() {
+        tags.push("project".to_string());
+    }
+
+    let workspaces: Vec<&str> = WORKSPACE_MARKERS
+        .iter()
+        .filter(|(marker, _)| dir.join(marker).is_file())
+        .map(|(_, kind)| *kind)
+        .chain(cargo_workspace(dir).then_some("cargo"))
+        .chain(npm_workspace(dir).then_some("npm"))
+        .collect();
+
+    if !workspaces.is_empty() {
+        tags.push("workspace".to_string());
+        tags.extend(workspaces.iter().map(|kind| format!("workspace:{}", kind)));
+    }
+
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+/// `dep:<name>` tags for what the manifests in a directory depend on, from
+/// `Cargo.toml`, `package.json`, `requirements.txt` and `go.mod`. `namespaced`
+/// puts the ecosystem in between, ie. `dep:cargo:serde`. Manifests that don't
+/// parse are skipped.
+pub fn dependency_tags(dir: &Path, namespaced: bool) -> Vec<String> {
+    let mut tags = Vec::new();
+
+    for (manifest, ecosystem, parse) in MANIFESTS {
+        let Ok(contents) = fs::read_to_string(dir.join(manifest)) else {
+            continue;
+        };
+
+        tags.extend(parse(&contents).into_iter().map(|name| match namespaced {
+            true => format!("dep:{}:{}", ecosystem, name),
+            false => format!("dep:{}", name),
+        }));
+    }
+
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+/// Every dependency table, including dev, build, workspace and per-target ones.
+/// Renamed dependencies count under their real package name.
+fn cargo_deps(contents: &str) -> Vec<String> {
+    let Ok(manifest) = contents.parse::<toml::Table>() else {
+        return Vec::new();
+    };
+
+    let mut sections = vec![&manifest];
+    if let Some(workspace) = manifest.get("workspace").and_then(|w| w.as_table()) {
+        sections.push(workspace);
+    }
+    if let Some(targets) = manifest.get("target").and_then(|t| t.as_table()) {
+        sections.extend(targets.values().filter_map(|target| target.as_table()));
+    }
+
+    sections
+        .into_iter()
+        .flat_map(|section| {
+            ["dependencies", "dev-dependencies", "build-dependencies"]
+                .into_iter()
+                .filter_map(|key| section.get(key)?.as_table())
+        })
+        .flat_map(|deps| deps.iter())
+        .map(|(name, dep)| {
+            dep.get("package")
+                .and_then(|package| package.as_str())
+                .unwrap_or(name)
+                .to_string()
+        })
+        .collect()
+}
+
+fn npm_deps(contents: &str) -> Vec<String> {
+    let Ok(manifest) = serde_json::from_str::<serde_json::Value>(contents) else {
+        return Vec::new();
+    };
+
+    [
+        "dependencies",
+        "devDependencies",
+        "peerDependencies",
+        "optionalDependencies",
+    ]
+    .iter()
+    .filter_map(|key| manifest.get(key)?.as_object())
+    .flat_map(|deps| deps.keys().cloned())
+    .collect()
+}
+
+/// Names are normalized like pip does, `Django_Rest` is `django-rest`.
+fn requirements_deps(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(|line| {
+            // Only a `#` at the start or after whitespace is a comment, urls can have `#egg=`
+            let end = line
+                .char_indices()
+                .find(|&(i, c)| c == '#' && (i == 0 || line[..i].ends_with(char::is_whitespace)))
+                .map_or(line.len(), |(i, _)| i);
+            line[..end].trim()
+        })
+        // Skip blanks and options like `-r other.txt` or `-e .`
+        .filter(|line| !line.is_empty() && !line.starts_with('-'))
+        .filter_map(|line| {
+            let name = match line.split_once("#egg=") {
+                Some((_, egg)) => egg,
+                None => line,
+            };
+            let end = name
+                .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+                .unwrap_or(name.len());
+            // Bare urls (`git+https://...`) and paths (`./vendor/pkg`) don't name the package
+            let rest = &name[end..];
+            if rest.starts_with([':', '/', '+', '\\']) || name.starts_with(['.', '/', '~']) {
+                return None;
+            }
+            let name = &name[..end];
+            (!name.is_empty()).then(|| name.to_lowercase().replace(['_', '.'], "-"))
+        })
+        .collect()
+}
+
+/// Direct requirements only, `// indirect` ones are dependencies of dependencies.
+fn go_deps(contents: &str) -> Vec<String> {
+    let mut deps = Vec::new();
+    let mut in_block = false;
+
+    for line in contents.lines().map(str::trim) {
+        let require = if in_block {
+            if line.starts_with(')') {
+                in_block = false;
+                continue;
+            }
+            line
+        } else if let Some(rest) = line.strip_prefix("require") {
+            let rest = rest.trim();
+            if rest.starts_with('(') {
+                in_block = true;
+                continue;
+            }
+            rest
+        } else {
+            continue;
+        };
+
+        if require.contains("// indirect") {
+            continue;
+        }
+        if let Some(module) = require.split_whitespace().next() {
+            if !module.starts_with("//") {
+                deps.push(module.to_string());
+            }
+        }
+    }
+
+    deps
+}
+
+/// A `[workspace]` table in `Cargo.toml`.
+fn cargo_workspace(dir: &Path) -> bool {
+    fs::read_to_string(dir.join("Cargo.toml"))
+        .ok()
+        .and_then(|manifest| manifest.parse::<toml::Table>().ok())
+        .is_some_and(|manifest| manifest.contains_key("workspace"))
+}
+
+/// A `workspaces` key in `package.json`, used by npm and yarn.
+fn npm_workspace(dir: &Path) -> bool {
+    fs::read_to_string(dir.join("package.json"))
+        .ok()
+        .and_then(|manifest| serde_json::from_str::<serde_json::Value>(&manifest).ok())
+        .is_some_and(|manifest| manifest.get("workspaces").is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_project_tags() -> std::io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let dir = temp_dir.path();
+        assert!(project_tags(dir).is_empty());
+
+        fs::write(dir.join("Cargo.toml"), "[package]\nname = \"x\"\n")?;
+        fs::write(dir.join("CMakeLists.txt"), "")?;
+        assert_eq!(project_tags(dir), vec!["cmake", "project", "rust"]);
+
+        fs::write(dir.join("Cargo.toml"), "[workspace]\nmembers = [\"a\"]\n")?;
+        fs::write(dir.join("package.json"), r#"{"workspaces": ["web"]}"#)?;
+        assert_eq!(
+            project_tags(dir),
+            vec![
+                "cmake",
+                "node",
+                "project",
+                "rust",
+                "workspace",
+                "workspace:cargo",
+                "workspace:npm"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest_deps() {
+        let cargo = r#"
+[dependencies]
+serde = { version = "1", features = ["derive"] }
+rand_core = { package = "rand", version = "0.8" }
+
+[dev-dependencies]
+tempfile = "3"
+
+[target.'cfg(unix)'.dependencies]
+libc = "0.2"
+"#;
+        let mut deps = cargo_deps(cargo);
+        deps.sort();
+        assert_eq!(deps, vec!["libc", "rand", "serde", "tempfile"]);
+
+        let npm = r#"{"dependencies": {"react": "^18"}, "devDependencies": {"vite": "5"}}"#;
+        assert_eq!(npm_deps(npm), vec!["react", "vite"]);
+
+        let requirements = "# pinned\nDjango_Rest>=3.0\nrequests[socks]==2.31 ; python_version > '3'\n-r dev.txt\n\nnumpy\n";
+        assert_eq!(
+            requirements_deps(requirements),
+            vec!["django-rest", "requests", "numpy"]
+        );
+
+        let urls = "git+https://github.com/a/b.git\n./vendor/pkg\nhttps://x.org/c.zip#egg=Some_Pkg\nflask @ https://x.org/flask.whl\nvendor/other\n";
+        assert_eq!(requirements_deps(urls), vec!["some-pkg", "flask"]);
+
+        let gomod = "module x\n\ngo 1.22\n\nrequire github.com/spf13/cobra v1.8.0\n\nrequire (\n\tgolang.org/x/sys v0.1.0\n\tgithub.com/a/b v1.0.0 // indirect\n)\n";
+        assert_eq!(
+            go_deps(gomod),
+            vec!["github.com/spf13/cobra", "golang.org/x/sys"]
+        );
+
+        assert!(cargo_deps("not [toml").is_empty());
+    }
+
+    #[test]
+    fn test_dependency_tags() -> std::io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let dir = temp_dir.path();
+        fs::write(dir.join("Cargo.toml"), "[dependencies]\nserde = \"1\"\n")?;
+        fs::write(dir.join("requirements.txt"), "serde\n")?;
+
+        assert_eq!(dependency_tags(dir, false), vec!["dep:serde"]);
+        assert_eq!(
+            dependency_tags(dir, true),
+            vec!["dep:cargo:serde", "dep:pypi:serde"]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+use std::{collections::BTreeSet, path::PathBuf};
+
+/// A tag search, built up and handed to [`TagStore::search`](crate::TagStore::search).
+///
+/// ```
+/// use stag::Query;
+///
+/// // Everything tagged rust and proj, but not wip
+/// let query = Query::new().tag("rust").tag("proj").exclude("wip");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Query {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub any: bool,
+    /// Only paths stored at or below this directory
+    pub under: Option<PathBuf>,
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require (or with [`Query::any`], allow) a tag.
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.include.push(tag.into());
+        self
+    }
+
+    pub fn tags<I, S>(mut self, tags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.include.extend(tags.into_iter().map(Into::into));
+        self
+    }
+
+    /// Drop every result that has this tag.
+    pub fn exclude(mut self, tag: impl Into<String>) -> Self {
+        self.exclude.push(tag.into());
+        self
+    }
+
+    pub fn excludes<I, S>(mut self, tags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.exclude.extend(tags.into_iter().map(Into::into));
+        self
+    }
+
+    /// Match paths with any of the tags instead of all of them.
+    pub fn any(mut self, any: bool) -> Self {
+        self.any = any;
+        self
+    }
+
+    /// Only match paths stored at or below `dir`. Goes by the stored paths,
+    /// so `dir` doesn't have to exist anymore.
+    pub fn under(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.under = Some(dir.into());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty()
+    }
+
+    /// Whether a path with `tags` is a result of this query.
+    pub fn matches(&self, tags: &BTreeSet<String>) -> bool {
+        if self.exclude.iter().any(|tag| tags.contains(tag)) {
+            return false;
+        }
+
+        if self.any {
+            self.include.iter().any(|tag| tags.contains(tag))
+        } else {
+            self.include.iter().all(|tag| tags.contains(tag))
+        }
+    }
+}
@@ -0,0 +1,467 @@
+use globset::{Glob, GlobBuilder, GlobMatcher};
+use serde::Deserialize;
+use std::{
+    fs,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use crate::{sniff::file_type, Error, Result};
+
+/// The `[autotag]` section of the config.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutotagConfig {
+    /// Keep the built-in tags (file/directory, size, MIME, ...) next to the rules
+    pub builtin: bool,
+    /// Put the ecosystem in dependency tags, `dep:cargo:serde` instead of `dep:serde`
+    pub namespace_deps: bool,
+    /// Repos without a commit in this many months get `git:stale`
+    pub git_stale_months: u32,
+    pub rules: Vec<Rule>,
+}
+
+impl Default for AutotagConfig {
+    fn default() -> Self {
+        Self {
+            builtin: true,
+            namespace_deps: false,
+            git_stale_months: 6,
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// One `[[autotag.rules]]` entry. Paths matching every condition that is set get
+/// all of `tags`, a rule without conditions matches everything.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rule {
+    /// Shown by `stag rules test`, defaults to `rule #N`
+    pub name: Option<String>,
+    pub tags: Vec<String>,
+    /// Just the file name if it has no `/`. Otherwise the end of the path, so
+    /// `src/*.rs` matches `~/code/app/src/main.rs`, unless it starts with `/`.
+    /// `*` stays within one path component, `**` crosses them
+    pub glob: Option<String>,
+    /// Any of these, case insensitive and without the dot
+    pub extensions: Vec<String>,
+    /// Glob on the MIME type, ie. `image/*`
+    pub mime: Option<String>,
+    /// Sizes like `512`, `100K`, `1.5M` or `2G`
+    pub min_size: Option<String>,
+    pub max_size: Option<String>,
+    /// Ages like `30s`, `15m`, `12h`, `7d` or `4w`, compared to the mtime
+    pub older_than: Option<String>,
+    pub newer_than: Option<String>,
+    /// Only directories containing a file or directory with this name
+    pub marker: Option<String>,
+}
+
+impl Rule {
+    pub fn name(&self, index: usize) -> String {
+        self.name.clone().unwrap_or(format!("rule #{}", index + 1))
+    }
+}
+
+#[derive(Debug)]
+struct Compiled {
+    name: String,
+    tags: Vec<String>,
+    glob: Option<(GlobMatcher, bool)>,
+    extensions: Vec<String>,
+    mime: Option<GlobMatcher>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    older_than: Option<Duration>,
+    newer_than: Option<Duration>,
+    marker: Option<String>,
+}
+
+/// A rule that matched, see [`RuleSet::matching`].
+#[derive(Debug, PartialEq)]
+pub struct Match<'a> {
+    pub name: &'a str,
+    pub tags: &'a [String],
+}
+
+/// Compiled autotag rules, cheap to clone onto the walker threads.
+#[derive(Debug, Clone)]
+pub struct RuleSet {
+    builtin: bool,
+    namespace_deps: bool,
+    git_stale_after: Duration,
+    rules: Arc<[Compiled]>,
+}
+
+impl Default for RuleSet {
+    /// Just the built-in tags.
+    fn default() -> Self {
+        Self::new(&AutotagConfig::default()).expect("no rules to get wrong")
+    }
+}
+
+impl RuleSet {
+    pub fn new(config: &AutotagConfig) -> Result<Self> {
+        let rules = config
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| compile(rule, rule.name(i)))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            builtin: config.builtin,
+            namespace_deps: config.namespace_deps,
+            git_stale_after: Duration::from_secs(config.git_stale_months as u64 * 30 * 86400),
+            rules: rules.into(),
+        })
+    }
+
+    /// Whether the built-in tags are generated too.
+    pub fn builtin(&self) -> bool {
+        self.builtin
+    }
+
+    /// Whether built-in `dep:` tags include the ecosystem.
+    pub fn namespace_deps(&self) -> bool {
+        self.namespace_deps
+    }
+
+    /// How old the last commit has to be for `git:stale`.
+    pub fn git_stale_after(&self) -> Duration {
+        self.git_stale_after
+    }
+
+    /// The rules `path` matches, in config order.
+    pub fn matching(&self, metadata: &fs::Metadata, path: &Path) -> Vec<Match<'_>> {
+        // Sniffing reads the file, only bother when a rule needs it
+        let mimes = match self.rules.iter().any(|rule| rule.mime.is_some()) {
+            true if metadata.is_file() => file_type(path).mimes,
+            _ => mime_guess::from_path(path)
+                .iter()
+                .map(|mime| mime.to_string())
+                .collect(),
+        };
+
+        self.rules
+            .iter()
+            .filter(|rule| rule.matches(metadata, path, &mimes))
+            .map(|rule| Match {
+                name: &rule.name,
+                tags: &rule.tags,
+            })
+            .collect()
+    }
+}
+
+impl Compiled {
+    fn matches(&self, metadata: &fs::Metadata, path: &Path, mimes: &[String]) -> bool {
+        if let Some((glob, whole_path)) = &self.glob {
+            let matched = match whole_path {
+                true => glob.is_match(path),
+                false => path.file_name().is_some_and(|name| glob.is_match(name)),
+            };
+            if !matched {
+                return false;
+            }
+        }
+
+        if !self.extensions.is_empty() {
+            let ext = path
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase());
+            if !ext.is_some_and(|ext| self.extensions.contains(&ext)) {
+                return false;
+            }
+        }
+
+        if let Some(mime) = &self.mime {
+            if !mimes.iter().any(|m| mime.is_match(m)) {
+                return false;
+            }
+        }
+
+        if self.min_size.is_some() || self.max_size.is_some() {
+            if !metadata.is_file() {
+                return false;
+            }
+            let size = metadata.len();
+            if self.min_size.is_some_and(|min| size < min)
+                || self.max_size.is_some_and(|max| size > max)
+            {
+                return false;
+            }
+        }
+
+        if self.older_than.is_some() || self.newer_than.is_some() {
+            let Some(age) = metadata
+                .modified()
+                .ok()
+                .and_then(|mtime| SystemTime::now().duration_since(mtime).ok())
+            else {
+                return false;
+            };
+            if self.older_than.is_some_and(|min| age < min)
+                || self.newer_than.is_some_and(|max| age > max)
+            {
+                return false;
+            }
+        }
+
+        if let Some(marker) = &self.marker {
+            if !metadata.is_dir() || !path.join(marker).exists() {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+fn compile(rule: &Rule, name: String) -> Result<Compiled> {
+    let invalid = |reason: String| Error::InvalidRule {
+        rule: name.clone(),
+        reason,
+    };
+
+    if rule.tags.is_empty() {
+        return Err(invalid("no tags to add".to_string()));
+    }
+
+    let glob = |pattern: &str| {
+        Glob::new(pattern)
+            .map(|glob| glob.compile_matcher())
+            .map_err(|err| invalid(err.to_string()))
+    };
+    let path_glob = |pattern: &str| {
+        GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map(|glob| glob.compile_matcher())
+            .map_err(|err| invalid(err.to_string()))
+    };
+    let size = |size: &Option<String>| {
+        size.as_deref()
+            .map(|s| parse_size(s).ok_or_else(|| invalid(format!("invalid size {:?}", s))))
+            .transpose()
+    };
+    let age = |age: &Option<String>| {
+        age.as_deref()
+            .map(|s| parse_age(s).ok_or_else(|| invalid(format!("invalid age {:?}", s))))
+            .transpose()
+    };
+
+    Ok(Compiled {
+        glob: match &rule.glob {
+            // Paths are absolute, a relative pattern can start anywhere in them
+            Some(pattern)
+                if pattern.contains('/')
+                    && !pattern.starts_with('/')
+                    && !pattern.starts_with("**") =>
+            {
+                Some((path_glob(&format!("**/{}", pattern))?, true))
+            }
+            Some(pattern) if pattern.contains('/') => Some((path_glob(pattern)?, true)),
+            Some(pattern) => Some((glob(pattern)?, false)),
+            None => None,
+        },
+        extensions: rule
+            .extensions
+            .iter()
+            .map(|ext| ext.trim_start_matches('.').to_lowercase())
+            .collect(),
+        mime: rule.mime.as_deref().map(glob).transpose()?,
+        min_size: size(&rule.min_size)?,
+        max_size: size(&rule.max_size)?,
+        older_than: age(&rule.older_than)?,
+        newer_than: age(&rule.newer_than)?,
+        marker: rule.marker.clone(),
+        tags: rule.tags.clone(),
+        name,
+    })
+}
+
+/// `100K` style sizes, in powers of 1024.
+fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+
+    let multiplier: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return None,
+    };
+
+    let number: f64 = number.trim().parse().ok()?;
+    (number >= 0.0).then_some((number * multiplier as f64) as u64)
+}
+
+fn parse_age(age: &str) -> Option<Duration> {
+    let age = age.trim();
+    let (number, unit) = age.split_at(age.len() - age.chars().last()?.len_utf8());
+
+    let seconds: u64 = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+
+    Some(Duration::from_secs(
+        number.trim().parse::<u64>().ok()?.checked_mul(seconds)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn rule(tags: &[&str]) -> Rule {
+        Rule {
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn names(rules: &RuleSet, path: &Path) -> Vec<String> {
+        let metadata = fs::metadata(path).unwrap();
+        rules
+            .matching(&metadata, path)
+            .into_iter()
+            .map(|m| m.name.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_units() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("100K"), Some(100 * 1024));
+        assert_eq!(parse_size("1.5m"), Some(3 * 512 * 1024));
+        assert_eq!(parse_size("2GiB"), Some(2 << 30));
+        assert_eq!(parse_size("lots"), None);
+
+        assert_eq!(parse_age("7d"), Some(Duration::from_secs(7 * 86400)));
+        assert_eq!(parse_age("15m"), Some(Duration::from_secs(900)));
+        assert_eq!(parse_age("7"), None);
+        assert_eq!(parse_age("99999999999999999w"), None);
+        assert_eq!(parse_age(""), None);
+    }
+
+    #[test]
+    fn test_matching() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let project = temp_dir.path().join("project");
+        fs::create_dir(&project)?;
+        fs::write(project.join("Cargo.toml"), "")?;
+        let photo = temp_dir.path().join("IMG_0001.JPG");
+        fs::write(&photo, vec![0; 2048])?;
+
+        let config = AutotagConfig {
+            builtin: false,
+            rules: vec![
+                Rule {
+                    name: Some("rust".to_string()),
+                    marker: Some("Cargo.toml".to_string()),
+                    ..rule(&["rust"])
+                },
+                Rule {
+                    name: Some("photos".to_string()),
+                    extensions: vec!["jpg".to_string()],
+                    mime: Some("image/*".to_string()),
+                    min_size: Some("1K".to_string()),
+                    newer_than: Some("1d".to_string()),
+                    ..rule(&["photo"])
+                },
+                Rule {
+                    name: Some("in project".to_string()),
+                    glob: Some("project/*.toml".to_string()),
+                    ..rule(&["config"])
+                },
+                Rule {
+                    glob: Some("IMG_*".to_string()),
+                    max_size: Some("1K".to_string()),
+                    ..rule(&["small-camera"])
+                },
+                rule(&["everything"]),
+            ],
+            ..Default::default()
+        };
+        let rules = RuleSet::new(&config)?;
+
+        assert_eq!(names(&rules, &project), vec!["rust", "rule #5"]);
+        assert_eq!(names(&rules, &photo), vec!["photos", "rule #5"]);
+        assert_eq!(
+            names(&rules, &project.join("Cargo.toml")),
+            vec!["in project", "rule #5"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_path_globs() -> Result<()> {
+        let matches = |pattern: &str, path: &str| -> Result<bool> {
+            let rule = Rule {
+                glob: Some(pattern.to_string()),
+                ..rule(&["x"])
+            };
+            let (glob, _) = compile(&rule, "test".to_string())?.glob.unwrap();
+            Ok(glob.is_match(path))
+        };
+
+        assert!(matches("src/*.rs", "/code/app/src/main.rs")?);
+        assert!(!matches("src/*.rs", "/code/app/src/a/b.rs")?);
+        assert!(matches("src/**/*.rs", "/code/app/src/a/b.rs")?);
+        assert!(matches("/code/*.rs", "/code/main.rs")?);
+        assert!(!matches("/code/*.rs", "/code/app/main.rs")?);
+        assert!(matches("**/target/**", "/code/app/target/debug/stag")?);
+        assert!(matches("*/src/*.rs", "/code/app/src/main.rs")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let bad = |rule: Rule| {
+            RuleSet::new(&AutotagConfig {
+                rules: vec![rule],
+                ..Default::default()
+            })
+            .unwrap_err()
+        };
+
+        assert!(matches!(bad(rule(&[])), Error::InvalidRule { .. }));
+        assert!(matches!(
+            bad(Rule {
+                min_size: Some("big".to_string()),
+                ..rule(&["x"])
+            }),
+            Error::InvalidRule { .. }
+        ));
+        assert!(matches!(
+            bad(Rule {
+                older_than: Some("99999999999999999w".to_string()),
+                ..rule(&["x"])
+            }),
+            Error::InvalidRule { .. }
+        ));
+        assert!(matches!(
+            bad(Rule {
+                glob: Some("[".to_string()),
+                ..rule(&["x"])
+            }),
+            Error::InvalidRule { .. }
+        ));
+    }
+}
@@ -0,0 +1,240 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{import::ImportReport, walk_paths, Error, Result, TagStore};
+
+/// Per-directory file listing tags for the directory and the files below it,
+/// meant to be committed so tags travel with a repository.
+pub const SIDECAR_FILE: &str = ".stag.toml";
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sidecar {
+    /// Tags of the directory itself
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Tags of paths below the directory, keyed by their relative path
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub files: BTreeMap<String, Vec<String>>,
+}
+
+impl Sidecar {
+    pub fn load(path: &Path) -> Result<Self> {
+        toml::from_str(&fs::read_to_string(path).map_err(Error::file(path))?).map_err(|source| {
+            Error::InvalidSidecar {
+                path: path.to_path_buf(),
+                source: Box::new(source),
+            }
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let toml = toml::to_string(self).expect("sidecars always serialize");
+        fs::write(path, toml).map_err(Error::file(path))?;
+        Ok(())
+    }
+}
+
+/// What [`import_sidecars`] did, on top of the usual import report.
+#[derive(Debug, Default)]
+pub struct SidecarImport {
+    pub report: ImportReport,
+    /// Paths a sidecar pointed outside of its own directory
+    pub outside: BTreeSet<PathBuf>,
+}
+
+/// Writes `dir/.stag.toml` with the stored tags of `dir` and everything below it,
+/// replacing any sidecar already there. Stored paths that no longer exist are left
+/// out. Returns how many paths were written.
+pub fn export_sidecar(store: &TagStore, dir: &Path) -> Result<usize> {
+    let dir = dir.canonicalize().map_err(|source| Error::Canonicalize {
+        path: dir.to_path_buf(),
+        source,
+    })?;
+
+    let mut sidecar = Sidecar::default();
+    let mut exported = 0;
+
+    for (path, tags) in store.tags_under(&dir)? {
+        if !path.exists() {
+            continue;
+        }
+
+        match path.strip_prefix(&dir) {
+            Ok(rel) if rel.as_os_str().is_empty() => sidecar.tags = tags,
+            Ok(rel) => {
+                sidecar
+                    .files
+                    .insert(rel.to_string_lossy().to_string(), tags);
+            }
+            Err(_) => continue,
+        }
+        exported += 1;
+    }
+
+    sidecar.save(&dir.join(SIDECAR_FILE))?;
+    Ok(exported)
+}
+
+/// Adds the tags from `dir/.stag.toml`, or from every sidecar below `dir` when
+/// `recursive`. Paths in a sidecar are relative to the directory it sits in,
+/// ones that don't exist end up in `missing`. Sidecars come with cloned repos, so
+/// paths that would leave that directory (absolute, `..`, symlinks out) end up in
+/// `outside` instead of tagging whatever they point at.
+pub fn import_sidecars(store: &mut TagStore, dir: &Path, recursive: bool) -> Result<SidecarImport> {
+    let sidecars: Vec<PathBuf> = if recursive {
+        walk_paths(vec![dir.to_path_buf()], true, true, 0)
+            .filter(|path| path.file_name().is_some_and(|name| name == SIDECAR_FILE))
+            .collect()
+    } else {
+        vec![dir.join(SIDECAR_FILE)]
+    };
+
+    let mut import = SidecarImport::default();
+    let mut tag_map: HashMap<String, Vec<PathBuf>> = HashMap::new();
+
+    for path in sidecars {
+        let sidecar = Sidecar::load(&path)?;
+        let base = path.parent().unwrap_or(dir);
+        let real_base = base.canonicalize().map_err(|source| Error::Canonicalize {
+            path: base.to_path_buf(),
+            source,
+        })?;
+
+        let mut entries = vec![(base.to_path_buf(), sidecar.tags)];
+        for (rel, tags) in sidecar.files {
+            let rel = PathBuf::from(rel);
+            match rel
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+            {
+                true => entries.push((base.join(rel), tags)),
+                false => {
+                    import.outside.insert(base.join(rel));
+                }
+            }
+        }
+
+        for (path, tags) in entries {
+            if tags.is_empty() {
+                continue;
+            }
+            if !path.exists() {
+                import.report.missing.insert(path);
+                continue;
+            }
+            if !path
+                .canonicalize()
+                .is_ok_and(|real| real.starts_with(&real_base))
+            {
+                import.outside.insert(path);
+                continue;
+            }
+
+            for tag in tags {
+                tag_map.entry(tag).or_default().push(path.clone());
+            }
+        }
+    }
+
+    for (tag, paths) in tag_map {
+        import.report.imported += store.add_tags_batch(&paths, &tag)?.changed;
+    }
+
+    Ok(import)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_sidecar_roundtrip() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let dir = temp_dir.path();
+        fs::create_dir(dir.join("src"))?;
+        fs::write(dir.join("src/main.rs"), "fn main() {}")?;
+
+        let mut store = TagStore::in_memory();
+        store.add_tags_batch(&[dir.to_path_buf()], "proj")?;
+        store.add_tags_batch(&[dir.join("src/main.rs")], "rust")?;
+
+        assert_eq!(export_sidecar(&store, dir)?, 2);
+
+        let sidecar = Sidecar::load(&dir.join(SIDECAR_FILE))?;
+        assert_eq!(sidecar.tags, vec!["proj"]);
+        assert_eq!(sidecar.files["src/main.rs"], vec!["rust"]);
+
+        let mut fresh = TagStore::in_memory();
+        let report = import_sidecars(&mut fresh, dir, false)?.report;
+        assert_eq!(report.imported, 2);
+        assert_eq!(fresh.get_file_tags(&dir.join("src/main.rs"))?, vec!["rust"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_missing_and_nested() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let dir = temp_dir.path();
+        fs::create_dir(dir.join("sub"))?;
+        fs::write(dir.join("sub/a.txt"), "a")?;
+        fs::write(
+            dir.join("sub").join(SIDECAR_FILE),
+            "[files]\n\"a.txt\" = [\"x\"]\n\"gone.txt\" = [\"x\"]\n",
+        )?;
+
+        let mut store = TagStore::in_memory();
+        let report = import_sidecars(&mut store, dir, true)?.report;
+
+        assert_eq!(report.imported, 1);
+        assert_eq!(
+            report.missing.into_iter().collect::<Vec<_>>(),
+            vec![dir.join("sub/gone.txt")]
+        );
+        assert_eq!(store.get_file_tags(&dir.join("sub/a.txt"))?, vec!["x"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_stays_inside() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let outside = temp_dir.path().join("secret.txt");
+        fs::write(&outside, "")?;
+        let repo = temp_dir.path().join("repo");
+        fs::create_dir(&repo)?;
+        fs::write(repo.join("ok.txt"), "")?;
+        std::os::unix::fs::symlink(&outside, repo.join("link"))?;
+        fs::write(
+            repo.join(SIDECAR_FILE),
+            format!(
+                "[files]\n\"ok.txt\" = [\"x\"]\n\"../secret.txt\" = [\"x\"]\n{:?} = [\"x\"]\n\"link\" = [\"x\"]\n",
+                outside.to_string_lossy()
+            ),
+        )?;
+
+        let mut store = TagStore::in_memory();
+        let import = import_sidecars(&mut store, &repo, false)?;
+
+        assert_eq!(import.report.imported, 1);
+        assert_eq!(import.outside.len(), 3);
+        assert!(store.get_file_tags(&outside)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_errors_name_the_file() {
+        let missing = Path::new("/nonexistent/.stag.toml");
+        let err = Sidecar::load(missing).unwrap_err();
+
+        assert!(matches!(err, Error::File { ref path, .. } if path == missing));
+        assert!(err.to_string().starts_with("/nonexistent/.stag.toml: "));
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+/// How much of a file is read to sniff it, enough for every magic below and a
+/// long shebang line.
+const HEADER_LEN: u64 = 512;
+
+struct Magic {
+    prefixes: &'static [&'static [u8]],
+    mime: &'static str,
+    /// Extensions that are fine for this content, ie. zip based formats
+    extensions: &'static [&'static str],
+}
+
+const MAGIC: &[Magic] = &[
+    Magic {
+        prefixes: &[b"\x7fELF"],
+        mime: "application/x-executable",
+        extensions: &["so", "o", "ko", "elf", "bin", "out", "axf", "prx"],
+    },
+    Magic {
+        prefixes: &[b"\x89PNG\r\n\x1a\n"],
+        mime: "image/png",
+        extensions: &["png"],
+    },
+    Magic {
+        prefixes: &[b"\xff\xd8\xff"],
+        mime: "image/jpeg",
+        extensions: &["jpg", "jpeg", "jpe", "jfif"],
+    },
+    Magic {
+        prefixes: &[b"%PDF-"],
+        mime: "application/pdf",
+        extensions: &["pdf"],
+    },
+    Magic {
+        prefixes: &[b"PK\x03\x04", b"PK\x05\x06", b"PK\x07\x08"],
+        mime: "application/zip",
+        extensions: &[
+            "zip", "jar", "war", "apk", "ipa", "docx", "xlsx", "pptx", "odt", "ods", "odp", "epub",
+            "xpi", "whl", "nupkg", "kmz", "3mf", "vsix", "cbz",
+        ],
+    },
+    Magic {
+        prefixes: &[b"\x1f\x8b"],
+        mime: "application/gzip",
+        extensions: &["gz", "tgz", "svgz", "gzip"],
+    },
+];
+
+/// What a file's first bytes say it is.
+#[derive(Debug, Default, PartialEq)]
+pub struct Sniffed {
+    pub mime: Option<&'static str>,
+    /// Interpreter from a `#!` line, without path or version (`python`)
+    pub interpreter: Option<String>,
+    extensions: &'static [&'static str],
+}
+
+/// The type of a file, sniffed from its content where possible and guessed from
+/// its extension otherwise.
+#[derive(Debug, Default, PartialEq)]
+pub struct FileType {
+    pub mimes: Vec<String>,
+    pub interpreter: Option<String>,
+    /// The content is something else than the extension claims
+    pub mismatched: bool,
+}
+
+pub fn sniff(path: &Path) -> io::Result<Sniffed> {
+    let mut header = Vec::new();
+    File::open(path)?
+        .take(HEADER_LEN)
+        .read_to_end(&mut header)?;
+    Ok(sniff_bytes(&header))
+}
+
+pub fn sniff_bytes(header: &[u8]) -> Sniffed {
+    if let Some(line) = header.strip_prefix(b"#!") {
+        let line = line.split(|&b| b == b'\n').next().unwrap_or_default();
+        return Sniffed {
+            interpreter: interpreter(&String::from_utf8_lossy(line)),
+            ..Default::default()
+        };
+    }
+
+    MAGIC
+        .iter()
+        .find(|magic| magic.prefixes.iter().any(|p| header.starts_with(p)))
+        .map(|magic| Sniffed {
+            mime: Some(magic.mime),
+            extensions: magic.extensions,
+            ..Default::default()
+        })
+        .unwrap_or_default()
+}
+
+/// Sniffs `path`, the sniffed type wins when it disagrees with the extension.
+/// Files that can't be read fall back to the extension.
+pub fn file_type(path: &Path) -> FileType {
+    let guessed: Vec<String> = mime_guess::from_path(path)
+        .iter()
+        .map(|mime| mime.to_string())
+        .collect();
+    let sniffed = sniff(path).unwrap_or_default();
+
+    let Some(mime) = sniffed.mime else {
+        return FileType {
+            mimes: guessed,
+            interpreter: sniffed.interpreter,
+            mismatched: false,
+        };
+    };
+
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    let agrees = guessed.iter().any(|guess| guess == mime)
+        || extension.is_some_and(|ext| sniffed.extensions.contains(&ext.as_str()));
+
+    // Unknown extensions don't claim anything, so they can't be wrong either
+    if agrees && !guessed.is_empty() {
+        return FileType {
+            mimes: guessed,
+            ..Default::default()
+        };
+    }
+
+    FileType {
+        mimes: vec![mime.to_string()],
+        interpreter: None,
+        mismatched: !agrees && !guessed.is_empty(),
+    }
+}
+
+/// `/usr/bin/env -S python3.12 -u` is `python`.
+fn interpreter(line: &str) -> Option<String> {
+    let mut words = line.split_whitespace();
+    let mut program = basename(words.next()?);
+
+    if program == "env" {
+        program = basename(words.find(|word| !word.starts_with('-') && !word.contains('='))?);
+    }
+
+    let name = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    match name.is_empty() {
+        true => Some(program.to_string()),
+        false => Some(name.to_string()),
+    }
+}
+
+fn basename(program: &str) -> &str {
+    program.rsplit('/').next().unwrap_or(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_sniff_bytes() {
+        let mime = |header: &[u8]| sniff_bytes(header).mime;
+
+        assert_eq!(mime(b"\x7fELF\x02\x01"), Some("application/x-executable"));
+        assert_eq!(mime(b"\x89PNG\r\n\x1a\n\0\0"), Some("image/png"));
+        assert_eq!(mime(b"\xff\xd8\xff\xe0"), Some("image/jpeg"));
+        assert_eq!(mime(b"%PDF-1.7"), Some("application/pdf"));
+        assert_eq!(mime(b"PK\x03\x04"), Some("application/zip"));
+        assert_eq!(mime(b"\x1f\x8b\x08"), Some("application/gzip"));
+        assert_eq!(mime(b"just text"), None);
+        assert_eq!(mime(b""), None);
+    }
+
+    #[test]
+    fn test_interpreter() {
+        let interp = |header: &[u8]| sniff_bytes(header).interpreter;
+
+        assert_eq!(interp(b"#!/bin/bash\necho"), Some("bash".to_string()));
+        assert_eq!(
+            interp(b"#!/usr/bin/env python3\n"),
+            Some("python".to_string())
+        );
+        assert_eq!(
+            interp(b"#! /usr/bin/env -S PYTHONUTF8=1 python3.12 -u\n"),
+            Some("python".to_string())
+        );
+        assert_eq!(interp(b"#!/usr/bin/node"), Some("node".to_string()));
+        assert_eq!(interp(b"#!\n"), None);
+    }
+
+    #[test]
+    fn test_file_type() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+        let misnamed = temp_dir.path().join("photo.jpg");
+        fs::write(&misnamed, png)?;
+        let kind = file_type(&misnamed);
+        assert_eq!(kind.mimes, vec!["image/png"]);
+        assert!(kind.mismatched);
+
+        let extensionless = temp_dir.path().join("photo");
+        fs::write(&extensionless, png)?;
+        assert_eq!(
+            file_type(&extensionless),
+            FileType {
+                mimes: vec!["image/png".to_string()],
+                ..Default::default()
+            }
+        );
+
+        // Zip based formats keep their own type
+        let docx = temp_dir.path().join("report.docx");
+        fs::write(&docx, b"PK\x03\x04")?;
+        let kind = file_type(&docx);
+        assert!(!kind.mismatched);
+        assert!(kind.mimes[0].contains("wordprocessingml"));
+
+        Ok(())
+    }
+}
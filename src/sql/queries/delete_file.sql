@@ -0,0 +1 @@
+DELETE FROM files WHERE id = ?1
@@ -0,0 +1 @@
+DELETE FROM file_tags WHERE file_id = ?1
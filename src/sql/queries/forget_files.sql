@@ -0,0 +1,2 @@
+DELETE FROM files
+WHERE path = ?1 OR (?2 AND path >= ?3 AND path < ?4)
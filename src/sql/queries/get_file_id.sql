@@ -0,0 +1 @@
+SELECT id FROM files WHERE path = ?1
@@ -0,0 +1,2 @@
+INSERT OR IGNORE INTO file_tags (file_id, tag_id)
+SELECT ?2, tag_id FROM file_tags WHERE file_id = ?1
@@ -0,0 +1 @@
+UPDATE files SET path = ?2 WHERE id = ?1
@@ -0,0 +1,4 @@
+SELECT id, path FROM files
+WHERE path = ?1 OR (path >= ?2 AND path < ?3)
+-- Shortest first, moving up a level (/a/b -> /a) frees /a/b/x before /a/b/b/x lands there
+ORDER BY length(path)
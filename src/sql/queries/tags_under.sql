@@ -0,0 +1,5 @@
+SELECT f.path, t.name FROM files f
+JOIN file_tags ft ON f.id = ft.file_id
+JOIN tags t ON ft.tag_id = t.id
+WHERE f.path = ?1 OR (f.path >= ?2 AND f.path < ?3)
+ORDER BY f.path, t.name
@@ -0,0 +1,5 @@
+SELECT f.directory, f.name, t.name, v.name
+FROM file_tag ft
+JOIN file f ON f.id = ft.file_id
+JOIN tag t ON t.id = ft.tag_id
+LEFT JOIN value v ON v.id = ft.value_id
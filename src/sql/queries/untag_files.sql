@@ -0,0 +1,5 @@
+DELETE FROM file_tags
+WHERE file_id IN (
+    SELECT id FROM files
+    WHERE path = ?1 OR (?2 AND path >= ?3 AND path < ?4)
+)
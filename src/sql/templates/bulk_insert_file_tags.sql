@@ -0,0 +1,3 @@
+INSERT OR IGNORE INTO file_tags (file_id, tag_id)
+SELECT f.id, ?1 FROM files f
+WHERE f.path IN ({path_placeholders})
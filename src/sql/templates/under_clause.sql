@@ -0,0 +1 @@
+AND (f.path = ? OR (f.path >= ? AND f.path < ?))
@@ -0,0 +1,140 @@
+//! Named stores, separate databases next to the default one so ie. work and
+//! personal tags don't mix. Picked with `--store <name>` or `store` in the config.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use directories::ProjectDirs;
+
+use crate::{backend::SqliteBackend, Error, Query, Result, TagStore};
+
+/// The store everything goes into unless another one is picked.
+pub const DEFAULT_STORE: &str = "default";
+
+pub(crate) fn project_dirs() -> Result<ProjectDirs> {
+    ProjectDirs::from("com", "stag", "stag").ok_or(Error::NoProjectDirs)
+}
+
+fn data_dir() -> Result<PathBuf> {
+    let data_dir = project_dirs()?.data_dir().to_path_buf();
+    fs::create_dir_all(&data_dir)?;
+    Ok(data_dir)
+}
+
+// Names end up in file names, keep them boring
+fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    if !valid {
+        return Err(Error::InvalidStoreName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Database file of a named store, which doesn't have to exist yet.
+/// The default store keeps living at `tags.db` in the data dir.
+pub fn store_path(name: &str) -> Result<PathBuf> {
+    validate_name(name)?;
+
+    if name == DEFAULT_STORE {
+        return Ok(data_dir()?.join("tags.db"));
+    }
+    Ok(data_dir()?.join("stores").join(format!("{}.db", name)))
+}
+
+/// Every named store, the default one first.
+pub fn list_stores() -> Result<Vec<String>> {
+    let mut names = Vec::new();
+
+    if let Ok(entries) = fs::read_dir(data_dir()?.join("stores")) {
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "db") {
+                if let Some(name) = path.file_stem() {
+                    names.push(name.to_string_lossy().to_string());
+                }
+            }
+        }
+    }
+
+    names.sort();
+    names.insert(0, DEFAULT_STORE.to_string());
+    Ok(names)
+}
+
+pub fn create_store(name: &str) -> Result<()> {
+    let path = store_path(name)?;
+    if name == DEFAULT_STORE || path.exists() {
+        return Err(Error::StoreExists(name.to_string()));
+    }
+
+    fs::create_dir_all(path.parent().expect("stores live in the data dir"))?;
+    SqliteBackend::open(&path)?;
+    Ok(())
+}
+
+/// Deletes a named store and its tags for good.
+pub fn delete_store(name: &str) -> Result<()> {
+    if name == DEFAULT_STORE {
+        return Err(Error::DeleteDefaultStore);
+    }
+
+    let path = store_path(name)?;
+    if !path.exists() {
+        return Err(Error::StoreNotFound(name.to_string()));
+    }
+
+    fs::remove_file(&path)?;
+    // WAL leftovers, only there if something still has it open or crashed
+    for suffix in ["-wal", "-shm"] {
+        let _ = fs::remove_file(sidecar(&path, suffix));
+    }
+    Ok(())
+}
+
+fn sidecar(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Runs `query` against each of the named stores, every result paired with
+/// the store it came from.
+pub fn search_stores(names: &[String], query: &Query) -> Result<Vec<(String, PathBuf)>> {
+    let mut results = Vec::new();
+
+    for name in names {
+        let store = TagStore::open_named(name)?;
+        results.extend(
+            store
+                .search(query)?
+                .into_iter()
+                .map(|path| (name.clone(), path)),
+        );
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("work").is_ok());
+        assert!(validate_name("side-project_2").is_ok());
+
+        for name in ["", "../etc", "a/b", "with space", "dots.db"] {
+            assert!(matches!(
+                validate_name(name),
+                Err(Error::InvalidStoreName(_))
+            ));
+        }
+    }
+}
@@ -1,187 +1,498 @@
-use std::path::PathBuf;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    backend::{
+        is_under, MemoryBackend, Relocation, SqliteBackend, TagBackend, DEFAULT_BUSY_TIMEOUT,
+    },
+    stores::{store_path, DEFAULT_STORE},
+    Config, Error, Query, Result,
+};
+
+/// How many paths the streaming operations resolve before handing them to the
+/// backend, each chunk is its own transaction.
+pub const STREAM_CHUNK_SIZE: usize = 10_000;
+
+/// What a tagging operation did. For adds `changed` counts newly tagged paths
+/// and `unchanged` the ones that already had the tag, for removes it's the
+/// paths that had the tag and the ones that didn't.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Changes {
+    pub changed: usize,
+    pub unchanged: usize,
+}
 
-use anyhow::{Context, Result};
-use directories::ProjectDirs;
-use rusqlite::{params, params_from_iter, Connection, ToSql, Transaction};
+impl Changes {
+    fn new(changed: usize, total: usize) -> Self {
+        Self {
+            changed,
+            unchanged: total - changed,
+        }
+    }
 
-pub struct TagStore {
-    conn: Connection,
+    pub fn total(&self) -> usize {
+        self.changed + self.unchanged
+    }
 }
 
-// SQL inject me, whatever
-// It's a local bundled database, why validate 5Head
-mod schemas {
-    pub const INIT_SQL: &str = include_str!("./sql/schema/init.sql");
+impl std::ops::AddAssign for Changes {
+    fn add_assign(&mut self, other: Self) {
+        self.changed += other.changed;
+        self.unchanged += other.unchanged;
+    }
 }
 
-mod queries {
-    pub const REMOVE_TAGS: &str = include_str!("./sql/queries/remove_tags.sql");
-    pub const LIST_TAGS: &str = include_str!("./sql/queries/list_tags.sql");
-    pub const GET_FILE_TAGS: &str = include_str!("./sql/queries/get_file_tags.sql");
+/// A single tag a dry run found would be added to or removed from a path.
+/// Displays as `+tag path` / `-tag path`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TagChange {
+    pub path: PathBuf,
+    pub tag: String,
+    pub added: bool,
 }
 
-mod templates {
-    pub const EXCLUDE_CLAUSE: &str = include_str!("./sql/templates/exclude_clause.sql");
-    pub const SEARCH_QUERY: &str = include_str!("./sql/templates/search_query.sql");
+impl fmt::Display for TagChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.added { '+' } else { '-' };
+        write!(f, "{}{} {}", sign, self.tag, self.path.display())
+    }
 }
 
-impl TagStore {
-    fn init_db(conn: &Connection) -> Result<()> {
-        conn.execute_batch(schemas::INIT_SQL)
-            .context("Failed to initialize the database schema")?;
+/// Directory at the root of a tree holding its portable store, see [`TagStore::open_portable`].
+pub const PORTABLE_DIR: &str = ".stag";
 
-        Ok(())
+/// Tags for paths on disk. Paths are canonicalized before they reach the
+/// backend, so `./foo`, `foo` and a symlink to it are all the same entry.
+pub struct TagStore {
+    backend: Box<dyn TagBackend>,
+    keys: PathKeys,
+}
+
+/// Turns absolute paths into what the backend stores and back. Portable stores
+/// keep paths relative to their root (`./sub/file`), so the tree can be mounted anywhere.
+#[derive(Default)]
+struct PathKeys {
+    root: Option<PathBuf>,
+}
+
+impl PathKeys {
+    fn key(&self, absolute: String) -> Result<String> {
+        let Some(root) = &self.root else {
+            return Ok(absolute);
+        };
+
+        match Path::new(&absolute).strip_prefix(root) {
+            Ok(relative) if relative.as_os_str().is_empty() => Ok(".".to_string()),
+            Ok(relative) => Ok(format!("./{}", relative.to_string_lossy())),
+            Err(_) => Err(Error::OutsideRoot {
+                path: PathBuf::from(absolute),
+                root: root.clone(),
+            }),
+        }
     }
 
-    pub fn new() -> anyhow::Result<Self> {
-        let conn = if let Ok(path) = std::env::var("STAG_DB_PATH") {
-            Connection::open(path)?
-        } else {
-            let proj_dirs = ProjectDirs::from("com", "stag", "stag")
-                .ok_or_else(|| anyhow::anyhow!("Could not determine project directories"))?;
+    fn resolve(&self, key: String) -> PathBuf {
+        match &self.root {
+            Some(root) if key == "." => root.clone(),
+            Some(root) => match key.strip_prefix("./") {
+                Some(relative) => root.join(relative),
+                None => PathBuf::from(key),
+            },
+            None => PathBuf::from(key),
+        }
+    }
 
-            let data_dir = proj_dirs.data_dir();
-            std::fs::create_dir_all(data_dir).context("Failed the create data directory")?;
+    fn existing(&self, path: &Path) -> Result<String> {
+        self.key(existing_stored_path(path)?)
+    }
 
-            Connection::open(data_dir.join("tags.db"))?
-        };
+    fn existing_all(&self, paths: &[PathBuf]) -> Result<Vec<String>> {
+        paths.iter().map(|path| self.existing(path)).collect()
+    }
+
+    fn lookup(&self, path: &Path) -> Result<String> {
+        self.key(lookup_path(path)?)
+    }
 
-        Self::init_db(&conn)?;
-        Ok(Self { conn })
+    fn lookup_all(&self, paths: &[PathBuf]) -> Result<Vec<String>> {
+        paths.iter().map(|path| self.lookup(path)).collect()
     }
 
-    // NOTE: Helper / Internal functions
-    fn get_or_create_tag(tx: &Transaction, tag: &str) -> Result<i64> {
-        let mut stmt = tx.prepare("INSERT OR IGNORE INTO tags (name) VALUES (?1)")?;
-        stmt.execute([tag])?;
+    fn resolve_all(&self, keys: Vec<String>) -> Vec<PathBuf> {
+        keys.into_iter().map(|key| self.resolve(key)).collect()
+    }
+}
 
-        let mut stmt = tx.prepare("SELECT id FROM tags WHERE name = ?1")?;
-        Ok(stmt.query_row([tag], |row| row.get(0))?)
+fn busy_timeout() -> Result<Duration> {
+    match std::env::var("STAG_BUSY_TIMEOUT") {
+        Ok(ms) => ms
+            .trim()
+            .parse()
+            .map(Duration::from_millis)
+            .map_err(|_| Error::InvalidBusyTimeout(ms)),
+        Err(_) => Ok(DEFAULT_BUSY_TIMEOUT),
     }
+}
 
-    fn get_or_create_file(tx: &Transaction, path: &PathBuf) -> Result<i64> {
-        if !path.exists() {
-            return Err(anyhow::anyhow!(
-                "Path does not exist: {}",
-                path.to_string_lossy()
-            ));
-        }
+/// The closest directory at or above `start` with a portable store in it, like git finds `.git`.
+pub fn find_portable_root(start: &Path) -> Option<PathBuf> {
+    find_portable_roots(start).into_iter().next()
+}
 
-        let canonical_path = path.canonicalize().context("Failed to canonicalize path")?;
-        let path_str = canonical_path.to_string_lossy();
+/// Every directory at or above `start` with a portable store in it, closest first.
+pub fn find_portable_roots(start: &Path) -> Vec<PathBuf> {
+    start
+        .ancestors()
+        .filter(|dir| portable_db(dir).is_file())
+        .map(Path::to_path_buf)
+        .collect()
+}
+
+pub(crate) fn portable_db(root: &Path) -> PathBuf {
+    root.join(PORTABLE_DIR).join("tags.db")
+}
 
-        let mut stmt = tx.prepare("INSERT OR IGNORE INTO files (path) VALUES (?1)")?;
-        stmt.execute([&path_str])?;
+fn canonicalize(path: &Path) -> Result<PathBuf> {
+    path.canonicalize().map_err(|source| Error::Canonicalize {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+fn stored_path(path: &Path) -> Result<String> {
+    Ok(canonicalize(path)?.to_string_lossy().to_string())
+}
 
-        let mut stmt = tx.prepare("SELECT id FROM files WHERE path = ?1")?;
-        Ok(stmt.query_row([&path_str], |row| row.get(0))?)
+/// Where `path` is (or was) stored. Paths that are gone can't be canonicalized,
+/// for those the closest ancestor that still exists is, with the rest appended.
+fn lookup_path(path: &Path) -> Result<String> {
+    if let Ok(path) = path.canonicalize() {
+        return Ok(path.to_string_lossy().to_string());
     }
 
-    // NOTE: Public API functions
-    pub fn add_tags_batch(&mut self, paths: &[PathBuf], tag: &str) -> Result<()> {
-        let tx = self.conn.transaction()?;
-        {
-            let tag_id = Self::get_or_create_tag(&tx, tag)?;
-
-            for path in paths {
-                let file_id = Self::get_or_create_file(&tx, path)?;
-                tx.execute(
-                    "INSERT OR IGNORE INTO file_tags (file_id, tag_id) VALUES (?1, ?2)",
-                    params![file_id, tag_id],
-                )?;
+    let absolute = std::path::absolute(path)?;
+    let path = absolute
+        .ancestors()
+        .skip(1)
+        .find_map(|ancestor| {
+            let tail = absolute.strip_prefix(ancestor).ok()?;
+            Some(normalize_lexically(
+                &ancestor.canonicalize().ok()?.join(tail),
+            ))
+        })
+        .unwrap_or_else(|| normalize_lexically(&absolute));
+
+    Ok(path.to_string_lossy().to_string())
+}
+
+// NOTE: Without the file there's no way to follow symlinks, `..` just drops a component
+fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
             }
+            other => normal.push(other),
         }
+    }
+    normal
+}
 
-        tx.commit()?;
-        Ok(())
+/// Like [`stored_path`], with a clearer error for paths that don't exist at all.
+fn existing_stored_path(path: &Path) -> Result<String> {
+    if !path.exists() {
+        return Err(Error::PathNotFound(path.to_path_buf()));
+    }
+    stored_path(path)
+}
+
+/// Feeds `paths` to `op` in chunks of [`STREAM_CHUNK_SIZE`], summing up the changes.
+fn for_each_chunk<I, F>(paths: I, mut op: F) -> Result<Changes>
+where
+    I: IntoIterator<Item = PathBuf>,
+    F: FnMut(&[PathBuf]) -> Result<Changes>,
+{
+    let mut chunk = Vec::with_capacity(STREAM_CHUNK_SIZE);
+    let mut changes = Changes::default();
+
+    for path in paths {
+        chunk.push(path);
+
+        if chunk.len() == STREAM_CHUNK_SIZE {
+            changes += op(&chunk)?;
+            chunk.clear();
+        }
+    }
+
+    if !chunk.is_empty() {
+        changes += op(&chunk)?;
     }
 
-    pub fn remove_tags_batch(&mut self, paths: &[PathBuf], tag: &str) -> Result<()> {
-        let tx = self.conn.transaction()?;
-        {
-            let mut stmt = tx.prepare(queries::REMOVE_TAGS)?;
+    Ok(changes)
+}
 
-            for path in paths {
-                let canonical_path = path.canonicalize().context("Failed to canonicalize path")?;
-                stmt.execute(params![canonical_path.to_string_lossy(), tag])?;
+impl TagStore {
+    /// Opens the default store. That's `$STAG_DB_PATH` if set, otherwise the closest
+    /// portable store above the working directory, otherwise the global store.
+    /// `$STAG_BUSY_TIMEOUT` (milliseconds) overrides how long to wait on other writers.
+    pub fn new() -> Result<Self> {
+        if std::env::var_os("STAG_DB_PATH").is_none() {
+            if let Some(root) = find_portable_root(&std::env::current_dir()?.canonicalize()?) {
+                let backend =
+                    SqliteBackend::open_with_timeout(portable_db(&root), busy_timeout()?)?;
+                return Self::with_backend(backend).with_root(root);
             }
         }
 
-        tx.commit()?;
-        Ok(())
+        Self::open_global()
     }
 
-    pub fn get_file_tags(&self, path: &PathBuf) -> Result<Vec<String>> {
-        let mut stmt = self.conn.prepare(queries::GET_FILE_TAGS)?;
+    /// Opens `$STAG_DB_PATH` if set, otherwise the store named in the config,
+    /// otherwise the default store in the XDG data dir. Portable stores are ignored.
+    pub fn open_global() -> Result<Self> {
+        if let Ok(path) = std::env::var("STAG_DB_PATH") {
+            let backend = SqliteBackend::open_with_timeout(path, busy_timeout()?)?;
+            return Ok(Self::with_backend(backend));
+        }
+
+        let name = Config::load()?.store;
+        Self::open_named(name.as_deref().unwrap_or(DEFAULT_STORE))
+    }
 
-        let canonical_path = path.canonicalize().context("Failed to canonicalize path")?;
-        let tags = stmt
-            .query_map([canonical_path.to_string_lossy()], |row| row.get(0))?
-            .collect::<Result<Vec<String>, _>>()?;
+    /// Opens a named store from the data dir, see [`stores`](crate::stores).
+    /// Other than the default store it has to be created first.
+    pub fn open_named(name: &str) -> Result<Self> {
+        let path = store_path(name)?;
+        if name != DEFAULT_STORE && !path.exists() {
+            return Err(Error::StoreNotFound(name.to_string()));
+        }
 
-        Ok(tags)
+        Ok(Self::with_backend(SqliteBackend::open_with_timeout(
+            path,
+            busy_timeout()?,
+        )?))
     }
 
-    pub fn list_tagged(&self, tag: &str) -> Result<Vec<PathBuf>> {
-        let mut stmt = self.conn.prepare(queries::LIST_TAGS)?;
+    /// Opens (and creates if needed) the portable store of the tree at `root`,
+    /// `.stag/tags.db` with paths stored relative to `root`.
+    pub fn open_portable(root: impl AsRef<Path>) -> Result<Self> {
+        let root = canonicalize(root.as_ref())?;
+        std::fs::create_dir_all(root.join(PORTABLE_DIR))?;
 
-        let paths = stmt
-            .query_map([tag], |row| {
-                let path_str: String = row.get(0)?;
-                Ok(PathBuf::from(path_str))
-            })?
-            .collect::<Result<Vec<_>, _>>()?;
+        Self::open(portable_db(&root))?.with_root(root)
+    }
 
-        Ok(paths)
+    /// Opens (and creates if needed) the SQLite store at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::with_backend(SqliteBackend::open(path)?))
     }
 
-    pub fn search_tags(
-        &self,
-        include_tags: &[&str],
-        exclude_tags: &[&str],
-        any: bool,
-    ) -> anyhow::Result<Vec<PathBuf>> {
-        if include_tags.is_empty() {
-            return Ok(Vec::new());
+    /// A SQLite store that only lives as long as the value.
+    pub fn open_in_memory() -> Result<Self> {
+        Ok(Self::with_backend(SqliteBackend::open_in_memory()?))
+    }
+
+    /// A store without any database at all, see [`MemoryBackend`].
+    pub fn in_memory() -> Self {
+        Self::with_backend(MemoryBackend::new())
+    }
+
+    pub fn with_backend(backend: impl TagBackend + 'static) -> Self {
+        Self {
+            backend: Box::new(backend),
+            keys: PathKeys::default(),
         }
+    }
 
-        let include_placeholders = vec!["?"; include_tags.len()].join(",");
-        let exclude_placeholders = if !exclude_tags.is_empty() {
-            vec!["?"; exclude_tags.len()].join(",")
-        } else {
-            String::new()
-        };
+    /// Stores paths relative to `root` from now on, paths outside of it are refused.
+    pub fn with_root(mut self, root: impl AsRef<Path>) -> Result<Self> {
+        self.keys.root = Some(canonicalize(root.as_ref())?);
+        Ok(self)
+    }
 
-        let having_clause = if any {
-            "HAVING COUNT(DISTINCT t.name) >= 1".into()
-        } else {
-            format!("HAVING COUNT(DISTINCT t.name) = {}", include_tags.len())
-        };
+    /// Root of a portable store, `None` for stores with absolute paths.
+    pub fn root(&self) -> Option<&Path> {
+        self.keys.root.as_deref()
+    }
 
-        let exclude_clause = if !exclude_tags.is_empty() {
-            templates::EXCLUDE_CLAUSE.replace("{exclude_placeholders}", &exclude_placeholders)
-        } else {
-            String::new()
-        };
+    // NOTE: Public API functions
+
+    /// Tags every path with `tag`. Either all paths get tagged or, if one of them
+    /// doesn't exist, none do.
+    pub fn add_tags_batch(&mut self, paths: &[PathBuf], tag: &str) -> Result<Changes> {
+        let added = self
+            .backend
+            .add_tags(&self.keys.existing_all(paths)?, tag)?;
+        Ok(Changes::new(added, paths.len()))
+    }
+
+    /// Tags a stream of paths, ie. straight from [`walk_paths`](crate::walk_paths), in
+    /// chunks of [`STREAM_CHUNK_SIZE`]. Memory use doesn't grow with the number of
+    /// paths, but unlike [`TagStore::add_tags_batch`] a failure only rolls back the
+    /// current chunk.
+    pub fn add_tags_stream<I>(&mut self, paths: I, tag: &str) -> Result<Changes>
+    where
+        I: IntoIterator<Item = PathBuf>,
+    {
+        let (backend, keys) = (&mut self.backend, &self.keys);
+        for_each_chunk(paths, |chunk| {
+            let added = backend.add_tags(&keys.existing_all(chunk)?, tag)?;
+            Ok(Changes::new(added, chunk.len()))
+        })
+    }
+
+    /// Untags a stream of paths in chunks, see [`TagStore::add_tags_stream`].
+    /// Paths that no longer exist are matched by their stored path.
+    pub fn remove_tags_stream<I>(&mut self, paths: I, tag: &str) -> Result<Changes>
+    where
+        I: IntoIterator<Item = PathBuf>,
+    {
+        let (backend, keys) = (&mut self.backend, &self.keys);
+        for_each_chunk(paths, |chunk| {
+            let removed = backend.remove_tags(&keys.lookup_all(chunk)?, tag)?;
+            Ok(Changes::new(removed, chunk.len()))
+        })
+    }
+
+    /// Untags every path, paths that no longer exist are matched by their stored path.
+    pub fn remove_tags_batch(&mut self, paths: &[PathBuf], tag: &str) -> Result<Changes> {
+        let removed = self
+            .backend
+            .remove_tags(&self.keys.lookup_all(paths)?, tag)?;
+        Ok(Changes::new(removed, paths.len()))
+    }
+
+    /// Untags everything stored at or below `dir` without walking it, so it works
+    /// for deleted directories and unmounted drives too.
+    pub fn remove_tags_under(&mut self, dir: &Path, tag: &str) -> Result<Changes> {
+        let query = Query::new().tag(tag).under(self.keys.lookup(dir)?);
+        let paths = self.backend.search(&query)?;
+
+        let removed = self.backend.remove_tags(&paths, tag)?;
+        Ok(Changes::new(removed, paths.len()))
+    }
+
+    /// Removes every tag from `paths`, and with `recursive` from everything stored
+    /// below them. `forget` drops the paths from the store entirely. Works for paths
+    /// that no longer exist, returns how many tag/path pairs were removed.
+    pub fn untag(&mut self, paths: &[PathBuf], recursive: bool, forget: bool) -> Result<usize> {
+        self.backend
+            .untag(&self.keys.lookup_all(paths)?, recursive, forget)
+    }
+
+    /// Rewrites every stored path at or below `from` to the same place below `to`,
+    /// ie. after moving a directory tree or mounting a disk somewhere else. Paths
+    /// already stored at the destination keep their tags and get the moved ones too.
+    /// `to` can't be below `from`, the other way around (`/a/b` to `/a`) is fine.
+    pub fn relocate(&mut self, from: &Path, to: &Path) -> Result<Relocation> {
+        let (from_path, to_path) = (self.keys.lookup(from)?, self.keys.lookup(to)?);
+
+        if from_path == to_path {
+            return Ok(Relocation::default());
+        }
+        // Moved paths could land on ones that still have to be moved
+        if is_under(&to_path, &from_path) {
+            return Err(Error::RelocateIntoItself {
+                from: from.to_path_buf(),
+                to: to.to_path_buf(),
+            });
+        }
+
+        self.backend.relocate(&from_path, &to_path)
+    }
 
-        let query = templates::SEARCH_QUERY
-            .replace("{include_placeholders}", &include_placeholders)
-            .replace("{exclude_clause}", &exclude_clause)
-            .replace("{having_clause}", &having_clause);
+    /// What adding (or removing) `tag` on `paths` would change, without writing anything.
+    /// Fails on missing paths the same way the real add does.
+    pub fn diff_tags<I>(&self, paths: I, tag: &str, add: bool) -> Result<Vec<TagChange>>
+    where
+        I: IntoIterator<Item = PathBuf>,
+    {
+        let mut tagged: HashSet<String> = self.backend.list_tagged(tag)?.into_iter().collect();
+        let mut changes = Vec::new();
+
+        for path in paths {
+            let stored = if add {
+                self.keys.existing(&path)?
+            } else {
+                self.keys.lookup(&path)?
+            };
+
+            // Keep the set up to date so a path walked twice only shows up once
+            let changed = if add {
+                tagged.insert(stored.clone())
+            } else {
+                tagged.remove(&stored)
+            };
+
+            if changed {
+                changes.push(TagChange {
+                    path: self.keys.resolve(stored),
+                    tag: tag.to_string(),
+                    added: add,
+                });
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Tags of `path`, which doesn't have to exist anymore.
+    pub fn get_file_tags(&self, path: &Path) -> Result<Vec<String>> {
+        self.backend.get_tags(&self.keys.lookup(path)?)
+    }
 
-        let mut stmt = self.conn.prepare(&query)?;
+    /// Every tagged path at or below `dir` with its tags, sorted by path.
+    pub fn tags_under(&self, dir: &Path) -> Result<BTreeMap<PathBuf, Vec<String>>> {
+        let mut tags: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
 
-        let mut params: Vec<&dyn ToSql> = include_tags.iter().map(|s| s as &dyn ToSql).collect();
-        params.extend(exclude_tags.iter().map(|s| s as &dyn ToSql));
+        for (path, tag) in self.backend.tags_under(&self.keys.lookup(dir)?)? {
+            tags.entry(self.keys.resolve(path)).or_default().push(tag);
+        }
+
+        Ok(tags)
+    }
 
-        let paths = stmt
-            .query_map(params_from_iter(params), |row| {
-                let path_str: String = row.get(0)?;
-                Ok(PathBuf::from(path_str))
-            })?
-            .collect::<Result<Vec<_>, _>>()?;
+    pub fn list_tagged(&self, tag: &str) -> Result<Vec<PathBuf>> {
+        Ok(self.keys.resolve_all(self.backend.list_tagged(tag)?))
+    }
+
+    pub fn search(&self, query: &Query) -> Result<Vec<PathBuf>> {
+        let mut resolved = query.clone();
+
+        if let Some(dir) = &query.under {
+            match self.keys.lookup(dir) {
+                Ok(key) => resolved.under = Some(PathBuf::from(key)),
+                // Above a portable store's root all of it is under `dir`, beside it none is
+                Err(Error::OutsideRoot { path, root }) if root.starts_with(&path) => {
+                    resolved.under = None
+                }
+                Err(Error::OutsideRoot { .. }) => return Ok(Vec::new()),
+                Err(err) => return Err(err),
+            }
+        }
+        let query = &resolved;
+
+        Ok(self.keys.resolve_all(self.backend.search(query)?))
+    }
+
+    pub fn search_tags(
+        &self,
+        include_tags: &[&str],
+        exclude_tags: &[&str],
+        any: bool,
+    ) -> Result<Vec<PathBuf>> {
+        let query = Query::new()
+            .tags(include_tags.iter().copied())
+            .excludes(exclude_tags.iter().copied())
+            .any(any);
 
-        Ok(paths)
+        self.search(&query)
     }
 }
 
@@ -191,10 +502,12 @@ mod tests {
     use std::fs;
     use tempfile::TempDir;
 
-    fn setup_test_db() -> anyhow::Result<TagStore> {
-        let conn = Connection::open_in_memory()?;
-        TagStore::init_db(&conn)?;
-        Ok(TagStore { conn })
+    fn setup_test_db() -> Result<TagStore> {
+        TagStore::open_in_memory()
+    }
+
+    fn set(paths: Vec<impl Into<String>>) -> HashSet<String> {
+        paths.into_iter().map(Into::into).collect()
     }
 
     #[test]
@@ -204,7 +517,7 @@ mod tests {
         let test_file = temp_dir.path().join("test_file");
         fs::write(&test_file, "test content")?;
 
-        store.add_tags_batch(&[test_file.clone()], "test_tag")?;
+        store.add_tags_batch(std::slice::from_ref(&test_file), "test_tag")?;
         let paths = store.list_tagged("test_tag")?;
 
         assert_eq!(paths.len(), 1);
@@ -219,8 +532,8 @@ mod tests {
         let test_file = temp_dir.path().join("test_file");
         fs::write(&test_file, "test content")?;
 
-        store.add_tags_batch(&[test_file.clone()], "tag1")?;
-        store.add_tags_batch(&[test_file.clone()], "tag2")?;
+        store.add_tags_batch(std::slice::from_ref(&test_file), "tag1")?;
+        store.add_tags_batch(std::slice::from_ref(&test_file), "tag2")?;
 
         let paths1 = store.list_tagged("tag1")?;
         let paths2 = store.list_tagged("tag2")?;
@@ -240,8 +553,8 @@ mod tests {
         fs::write(&file1, "test content")?;
         fs::write(&file2, "test content")?;
 
-        store.add_tags_batch(&[file1.clone()], "shared_tag")?;
-        store.add_tags_batch(&[file2.clone()], "shared_tag")?;
+        store.add_tags_batch(std::slice::from_ref(&file1), "shared_tag")?;
+        store.add_tags_batch(std::slice::from_ref(&file2), "shared_tag")?;
 
         let paths = store.list_tagged("shared_tag")?;
         assert_eq!(paths.len(), 2);
@@ -257,8 +570,8 @@ mod tests {
         let test_file = temp_dir.path().join("test_file");
         fs::write(&test_file, "test content")?;
 
-        store.add_tags_batch(&[test_file.clone()], "test_tag")?;
-        store.remove_tags_batch(&[test_file.clone()], "test_tag")?;
+        store.add_tags_batch(std::slice::from_ref(&test_file), "test_tag")?;
+        store.remove_tags_batch(std::slice::from_ref(&test_file), "test_tag")?;
 
         let paths = store.list_tagged("test_tag")?;
         assert!(paths.is_empty());
@@ -291,16 +604,16 @@ mod tests {
         let test_file = temp_dir.path().join("test_file");
         fs::write(&test_file, "test content")?;
 
-        store.add_tags_batch(&[test_file.clone()], "tag1")?;
-        store.add_tags_batch(&[test_file.clone()], "tag2")?;
+        store.add_tags_batch(std::slice::from_ref(&test_file), "tag1")?;
+        store.add_tags_batch(std::slice::from_ref(&test_file), "tag2")?;
 
-        store.remove_tags_batch(&[test_file.clone()], "tag1")?;
+        store.remove_tags_batch(std::slice::from_ref(&test_file), "tag1")?;
 
         // File should still exist in files table
         let paths = store.list_tagged("tag2")?;
         assert_eq!(paths.len(), 1);
 
-        store.remove_tags_batch(&[test_file.clone()], "tag2")?;
+        store.remove_tags_batch(std::slice::from_ref(&test_file), "tag2")?;
 
         // File should be cleaned up
         let paths = store.list_tagged("tag2")?;
@@ -333,6 +646,168 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_diff_tags() -> anyhow::Result<()> {
+        let mut store = setup_test_db()?;
+        let temp_dir = TempDir::new()?;
+        let tagged = temp_dir.path().join("tagged");
+        let untagged = temp_dir.path().join("untagged");
+        fs::write(&tagged, "test")?;
+        fs::write(&untagged, "test")?;
+
+        store.add_tags_batch(std::slice::from_ref(&tagged), "rust")?;
+
+        let paths = vec![tagged.clone(), untagged.clone(), untagged.clone()];
+        let added = store.diff_tags(paths.clone(), "rust", true)?;
+        assert_eq!(
+            added,
+            vec![TagChange {
+                path: untagged.canonicalize()?,
+                tag: "rust".to_string(),
+                added: true,
+            }]
+        );
+        assert_eq!(
+            added[0].to_string(),
+            format!("+rust {}", untagged.canonicalize()?.display())
+        );
+
+        let removed = store.diff_tags(paths, "rust", false)?;
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].path, tagged.canonicalize()?);
+
+        // Nothing was written
+        assert_eq!(store.list_tagged("rust")?, vec![tagged.canonicalize()?]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_untag_deleted_paths() -> anyhow::Result<()> {
+        let mut store = setup_test_db()?;
+        let temp_dir = TempDir::new()?;
+        let dir = temp_dir.path().join("dir");
+        let file = dir.join("file");
+        fs::create_dir(&dir)?;
+        fs::write(&file, "test")?;
+
+        store.add_tags_batch(&[dir.clone(), file.clone()], "one")?;
+        store.add_tags_batch(std::slice::from_ref(&file), "two")?;
+        fs::remove_dir_all(&dir)?;
+
+        // Gone from disk, still matched by the stored string
+        assert_eq!(store.untag(&[dir.join("sub/../file")], false, false)?, 2);
+        assert_eq!(store.list_tagged("one")?.len(), 1);
+
+        assert_eq!(store.untag(&[dir], true, true)?, 1);
+        assert!(store.list_tagged("one")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_and_inspect_deleted_path() -> anyhow::Result<()> {
+        let mut store = setup_test_db()?;
+        let temp_dir = TempDir::new()?;
+        let test_file = temp_dir.path().join("test_file");
+        fs::write(&test_file, "test")?;
+
+        store.add_tags_batch(std::slice::from_ref(&test_file), "keep")?;
+        store.add_tags_batch(std::slice::from_ref(&test_file), "stale")?;
+        fs::remove_file(&test_file)?;
+
+        let relative = temp_dir.path().join("./other/../test_file");
+        assert_eq!(store.get_file_tags(&relative)?.len(), 2);
+
+        let changes = store.remove_tags_batch(&[relative], "stale")?;
+        assert_eq!(changes, Changes::new(1, 1));
+        assert_eq!(store.get_file_tags(&test_file)?, vec!["keep"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_under() -> anyhow::Result<()> {
+        let mut store = setup_test_db()?;
+        let temp_dir = TempDir::new()?;
+        let project = temp_dir.path().join("project");
+        let other = temp_dir.path().join("project-other");
+        fs::create_dir(&project)?;
+        fs::create_dir(&other)?;
+        fs::write(project.join("file"), "test")?;
+
+        let paths = vec![project.clone(), project.join("file"), other.clone()];
+        store.add_tags_batch(&paths, "tag")?;
+        fs::remove_dir_all(&project)?;
+
+        let query = Query::new()
+            .tag("tag")
+            .under(temp_dir.path().join("./project/"));
+        assert_eq!(store.search(&query)?.len(), 2);
+
+        let changes = store.remove_tags_under(&project, "tag")?;
+        assert_eq!(changes, Changes::new(2, 2));
+        assert_eq!(store.list_tagged("tag")?, vec![other.canonicalize()?]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_relocate() -> anyhow::Result<()> {
+        let mut store = setup_test_db()?;
+        let temp_dir = TempDir::new()?;
+        let old = temp_dir.path().join("old");
+        let new = temp_dir.path().join("new");
+        fs::create_dir(&old)?;
+        fs::write(old.join("file"), "test")?;
+
+        store.add_tags_batch(&[old.clone(), old.join("file")], "tag")?;
+        fs::rename(&old, &new)?;
+
+        assert_eq!(store.relocate(&old, &new)?.moved, 2);
+        assert_eq!(store.get_file_tags(&new.join("file"))?, vec!["tag"]);
+        assert_eq!(store.relocate(&new, &new)?.moved, 0);
+
+        let err = store.relocate(&new, &new.join("sub")).unwrap_err();
+        assert!(matches!(err, Error::RelocateIntoItself { .. }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_portable_store() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let mount = temp_dir.path().join("mount");
+        let moved = temp_dir.path().join("moved");
+        fs::create_dir_all(mount.join("photos"))?;
+
+        let mut store = TagStore::open_portable(&mount)?;
+        store.add_tags_batch(&[mount.clone(), mount.join("photos")], "nas")?;
+        assert_eq!(
+            set(store.backend.list_tagged("nas")?),
+            set(vec![".", "./photos"])
+        );
+
+        let err = store
+            .add_tags_batch(&[temp_dir.path().to_path_buf()], "nas")
+            .unwrap_err();
+        assert!(matches!(err, Error::OutsideRoot { .. }));
+        drop(store);
+
+        // Mounted somewhere else, same tags
+        fs::rename(&mount, &moved)?;
+        assert_eq!(
+            find_portable_root(&moved.join("photos")),
+            Some(moved.canonicalize()?)
+        );
+
+        let store = TagStore::open_portable(&moved)?;
+        let under = |dir: &Path| Query::new().tag("nas").under(dir);
+        assert_eq!(
+            store.search(&under(&moved.join("photos")))?,
+            vec![moved.join("photos").canonicalize()?]
+        );
+        assert_eq!(store.search(&under(temp_dir.path()))?.len(), 2);
+        assert!(store.search(&under(&mount))?.is_empty());
+        assert_eq!(store.get_file_tags(&moved)?, vec!["nas"]);
+        Ok(())
+    }
+
     #[test]
     fn test_batch_transaction_rollback() -> anyhow::Result<()> {
         let mut store = setup_test_db()?;
@@ -388,8 +863,8 @@ mod tests {
         fs::write(&file1, "test")?;
 
         // File with both tags
-        store.add_tags_batch(&[file1.clone()], "tag1")?;
-        store.add_tags_batch(&[file1.clone()], "tag2")?;
+        store.add_tags_batch(std::slice::from_ref(&file1), "tag1")?;
+        store.add_tags_batch(std::slice::from_ref(&file1), "tag2")?;
 
         // Test AND search
         let results = store.search_tags(&["tag1", "tag2"], &[], false)?;
@@ -449,7 +924,7 @@ mod tests {
 
         let special_tags = vec!["태그", "标签", "🏷️", "tag with spaces", "@#$%"];
         for tag in special_tags {
-            store.add_tags_batch(&[test_file.clone()], tag)?;
+            store.add_tags_batch(std::slice::from_ref(&test_file), tag)?;
             let paths = store.list_tagged(tag)?;
             assert_eq!(paths.len(), 1);
         }
@@ -466,13 +941,37 @@ mod tests {
         fs::write(&real_file, "test")?;
         std::os::unix::fs::symlink(&real_file, &symlink)?;
 
-        store.add_tags_batch(&[symlink.clone()], "tag")?;
+        store.add_tags_batch(std::slice::from_ref(&symlink), "tag")?;
         let paths = store.list_tagged("tag")?;
         assert_eq!(paths[0], real_file.canonicalize()?);
 
         Ok(())
     }
 
+    #[test]
+    fn test_deleted_path_under_symlink() -> Result<()> {
+        let mut store = setup_test_db()?;
+        let temp_dir = TempDir::new()?;
+        let real = temp_dir.path().join("real");
+        let link = temp_dir.path().join("link");
+        fs::create_dir(&real)?;
+        std::os::unix::fs::symlink(&real, &link)?;
+        let file = real.join("gone.txt");
+        fs::write(&file, "")?;
+
+        store.add_tags_batch(&[link.join("gone.txt")], "rust")?;
+        fs::remove_file(&file)?;
+
+        // Still found through the symlink, even with the file gone
+        assert_eq!(store.get_file_tags(&link.join("gone.txt"))?, vec!["rust"]);
+        assert_eq!(
+            store.get_file_tags(&link.join("sub/../gone.txt"))?,
+            vec!["rust"]
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn test_large_tag_name() -> Result<()> {
         let mut store = setup_test_db()?;
@@ -485,4 +984,49 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_stream_chunking() -> Result<()> {
+        let paths = (0..STREAM_CHUNK_SIZE * 2 + 1).map(|i| PathBuf::from(format!("/{}", i)));
+
+        let mut chunks = Vec::new();
+        let changes = for_each_chunk(paths, |chunk| {
+            chunks.push(chunk.len());
+            Ok(Changes::new(1, chunk.len()))
+        })?;
+
+        assert_eq!(changes.total(), STREAM_CHUNK_SIZE * 2 + 1);
+        assert_eq!(changes.changed, 3);
+        assert_eq!(chunks, vec![STREAM_CHUNK_SIZE, STREAM_CHUNK_SIZE, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_and_remove_stream() -> Result<()> {
+        let mut store = setup_test_db()?;
+        let temp_dir = TempDir::new()?;
+        fs::create_dir(temp_dir.path().join("nested"))?;
+        for i in 0..3 {
+            fs::write(
+                temp_dir.path().join("nested").join(format!("file{}", i)),
+                "",
+            )?;
+        }
+
+        let walked = crate::walk_paths(vec![temp_dir.path().to_path_buf()], true, false, 1);
+        assert_eq!(store.add_tags_stream(walked, "tag")?, Changes::new(5, 5));
+        assert_eq!(store.list_tagged("tag")?.len(), 5);
+
+        // Second time around everything is already tagged
+        let walked = crate::walk_paths(vec![temp_dir.path().to_path_buf()], true, false, 1);
+        assert_eq!(store.add_tags_stream(walked, "tag")?, Changes::new(0, 5));
+
+        let walked = crate::walk_paths(vec![temp_dir.path().join("nested")], true, false, 0);
+        store.remove_tags_stream(walked, "tag")?;
+        assert_eq!(
+            store.list_tagged("tag")?,
+            vec![temp_dir.path().canonicalize()?]
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,463 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{Error, Query, Result, TagStore};
+
+/// File inside a view directory remembering the query it was built from,
+/// so `--refresh` can rebuild it without repeating the tags.
+pub const VIEW_MANIFEST: &str = ".stag-view";
+
+/// Where nested views keep their tag folders, apart from the links to the results.
+pub const TAG_FOLDERS: &str = "by-tag";
+
+/// Most tag folders a single result is linked into, a file with lots of tags
+/// would otherwise be in every combination of them.
+const MAX_TAG_FOLDERS: usize = 256;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ViewQuery {
+    pub tags: Vec<String>,
+    pub exclude: Vec<String>,
+    pub any: bool,
+    /// Depth of the per-tag folder layout, `None` for a flat view
+    pub nested: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+pub struct ViewReport {
+    pub added: usize,
+    pub removed: usize,
+}
+
+impl ViewQuery {
+    // NOTE: One entry per line since tags are allowed to contain spaces
+    fn serialize(&self) -> String {
+        let mut out = String::new();
+        for tag in &self.tags {
+            out.push_str(&format!("include {}\n", tag));
+        }
+        for tag in &self.exclude {
+            out.push_str(&format!("exclude {}\n", tag));
+        }
+        if self.any {
+            out.push_str("any\n");
+        }
+        if let Some(depth) = self.nested {
+            out.push_str(&format!("nested {}\n", depth));
+        }
+        out
+    }
+
+    fn parse(manifest: &str) -> Result<Self> {
+        let mut query = ViewQuery::default();
+
+        for line in manifest.lines().filter(|l| !l.is_empty()) {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "include" => query.tags.push(value.to_string()),
+                "exclude" => query.exclude.push(value.to_string()),
+                "any" => query.any = true,
+                "nested" => {
+                    let depth = value
+                        .parse()
+                        .map_err(|_| Error::InvalidViewManifest(line.to_string()))?;
+                    query.nested = Some(depth);
+                }
+                _ => return Err(Error::InvalidViewManifest(line.to_string())),
+            }
+        }
+
+        Ok(query)
+    }
+
+    pub fn load(dir: &Path) -> Result<Self> {
+        let manifest = fs::read_to_string(dir.join(VIEW_MANIFEST))
+            .map_err(|_| Error::NotAView(dir.to_path_buf()))?;
+        Self::parse(&manifest)
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(VIEW_MANIFEST);
+        fs::write(&path, self.serialize()).map_err(Error::file(&path))?;
+        Ok(())
+    }
+}
+
+/// Builds or incrementally updates `dir` so it contains one symlink per search result.
+/// Only symlinks are ever removed, anything else placed in the view is left alone.
+pub fn build_view(store: &TagStore, dir: &Path, query: &ViewQuery) -> Result<ViewReport> {
+    if dir.exists() && !dir.join(VIEW_MANIFEST).exists() && fs::read_dir(dir)?.next().is_some() {
+        return Err(Error::ViewDirNotEmpty(dir.to_path_buf()));
+    }
+
+    fs::create_dir_all(dir).map_err(Error::file(dir))?;
+    query.save(dir)?;
+
+    let wanted = wanted_links(store, query)?;
+    let existing = existing_links(dir)?;
+    let mut report = ViewReport::default();
+
+    for (link, target) in &existing {
+        if wanted.get(link) != Some(target) {
+            fs::remove_file(dir.join(link))?;
+            report.removed += 1;
+        }
+    }
+
+    for (link, target) in &wanted {
+        if existing.get(link) == Some(target) {
+            continue;
+        }
+
+        let link = dir.join(link);
+        if let Some(parent) = link.parent() {
+            create_view_dirs(dir, parent)?;
+        }
+        std::os::unix::fs::symlink(target, &link).map_err(Error::file(&link))?;
+        report.added += 1;
+    }
+
+    remove_empty_dirs(dir)?;
+    Ok(report)
+}
+
+fn wanted_links(store: &TagStore, query: &ViewQuery) -> Result<BTreeMap<PathBuf, PathBuf>> {
+    let search = Query::new()
+        .tags(query.tags.iter().cloned())
+        .excludes(query.exclude.iter().cloned())
+        .any(query.any);
+
+    // Dangling links are useless in a file manager, skip paths that are gone
+    let mut targets: Vec<PathBuf> = store
+        .search(&search)?
+        .into_iter()
+        .filter(|p| p.exists())
+        .collect();
+    targets.sort();
+
+    let mut links = BTreeMap::new();
+    let reserved = query.nested.map(|_| TAG_FOLDERS);
+    let names = link_names(&targets, reserved);
+
+    for (target, name) in targets.iter().zip(names) {
+        links.insert(PathBuf::from(&name), target.clone());
+
+        if let Some(depth) = query.nested {
+            // Tags already in the query would just repeat the whole view one level down
+            let tags: Vec<String> = store
+                .get_file_tags(target)?
+                .into_iter()
+                .filter(|t| !query.tags.contains(t))
+                .collect();
+
+            for folder in tag_folders(&tags, depth) {
+                let link = Path::new(TAG_FOLDERS).join(folder).join(&name);
+                links.insert(link, target.clone());
+            }
+        }
+    }
+
+    Ok(links)
+}
+
+/// Every combination of up to `depth` tags, each in sorted order so the same set
+/// of tags is only one folder, ie. `proj`, `rust` and `proj/rust`. Stops after
+/// [`MAX_TAG_FOLDERS`], shallow folders first.
+fn tag_folders(tags: &[String], depth: usize) -> Vec<PathBuf> {
+    let mut tags: Vec<&String> = tags.iter().collect();
+    tags.sort();
+    tags.dedup();
+
+    let mut folders = Vec::new();
+    // Folder and the index of the next tag it may be extended with
+    let mut frontier = vec![(PathBuf::new(), 0)];
+
+    for _ in 0..depth {
+        let mut next = Vec::new();
+        for (folder, start) in &frontier {
+            for (i, tag) in tags.iter().enumerate().skip(*start) {
+                if folders.len() + next.len() >= MAX_TAG_FOLDERS {
+                    break;
+                }
+                next.push((folder.join(sanitize(tag)), i + 1));
+            }
+        }
+        folders.extend(next.iter().map(|(folder, _)| folder.clone()));
+        frontier = next;
+    }
+
+    folders
+}
+
+/// Picks a link name for each target. Unique basenames are used as-is, identical ones
+/// get a short hash of the full path, which stays stable across refreshes. Should
+/// two of those still clash, they get the full hash instead. A `reserved` name is
+/// treated as taken.
+fn link_names(targets: &[PathBuf], reserved: Option<&str>) -> Vec<String> {
+    let basename = |p: &PathBuf| {
+        p.file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| "root".to_string())
+    };
+    let hashed = |target: &PathBuf, name: &str, short: bool| {
+        let hash = fnv1a(target.to_string_lossy().as_bytes());
+        let (hash, width) = match short {
+            true => (hash & 0xff_ffff, 6),
+            false => (hash, 16),
+        };
+        match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => {
+                format!("{}~{:0width$x}.{}", stem, hash, ext)
+            }
+            _ => format!("{}~{:0width$x}", name, hash),
+        }
+    };
+
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    counts.extend(reserved.map(|name| (name.to_string(), 1)));
+    for target in targets {
+        *counts.entry(basename(target)).or_default() += 1;
+    }
+
+    let mut names: Vec<(String, bool)> = targets
+        .iter()
+        .map(|target| {
+            let name = basename(target);
+            match counts[&name] {
+                1 => (name, false),
+                _ => (hashed(target, &name, true), true),
+            }
+        })
+        .collect();
+
+    let mut taken: HashMap<String, usize> = HashMap::new();
+    for (name, _) in &names {
+        *taken.entry(name.clone()).or_default() += 1;
+    }
+
+    for (target, (name, is_hashed)) in targets.iter().zip(&mut names) {
+        if *is_hashed && taken[name.as_str()] > 1 {
+            *name = hashed(target, &basename(target), false);
+        }
+    }
+
+    names.into_iter().map(|(name, _)| name).collect()
+}
+
+// std's DefaultHasher isn't guaranteed stable between releases, link names must be
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Tags as folder names. `/` would nest and `.`, `..` or nothing would point
+/// somewhere else entirely, so those get percent encoded.
+fn sanitize(tag: &str) -> String {
+    match tag {
+        "" => "%00".to_string(),
+        "." => "%2E".to_string(),
+        ".." => "%2E%2E".to_string(),
+        _ => tag.replace('%', "%25").replace('/', "%2F"),
+    }
+}
+
+/// Like `fs::create_dir_all` for `folder` inside the view, except that it won't go
+/// through a symlink (or anything else) in the way.
+fn create_view_dirs(dir: &Path, folder: &Path) -> Result<()> {
+    let mut current = dir.to_path_buf();
+
+    for component in folder.strip_prefix(dir).unwrap_or(folder).components() {
+        current.push(component);
+        match fs::symlink_metadata(&current) {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => {
+                let err = io::Error::new(io::ErrorKind::AlreadyExists, "not a folder");
+                return Err(Error::file(&current)(err));
+            }
+            Err(_) => fs::create_dir(&current).map_err(Error::file(&current))?,
+        }
+    }
+
+    Ok(())
+}
+
+fn existing_links(dir: &Path) -> Result<BTreeMap<PathBuf, PathBuf>> {
+    let mut links = BTreeMap::new();
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+
+            if file_type.is_symlink() {
+                let relative = entry
+                    .path()
+                    .strip_prefix(dir)
+                    .expect("walked entries are inside the view")
+                    .to_path_buf();
+                links.insert(relative, fs::read_link(entry.path())?);
+            } else if file_type.is_dir() {
+                pending.push(entry.path());
+            }
+        }
+    }
+
+    Ok(links)
+}
+
+fn remove_empty_dirs(dir: &Path) -> Result<bool> {
+    let mut empty = true;
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() && remove_empty_dirs(&entry.path())? {
+            fs::remove_dir(entry.path())?;
+        } else {
+            empty = false;
+        }
+    }
+
+    Ok(empty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_names_unique() {
+        let names = link_names(
+            &[PathBuf::from("/a/main.rs"), PathBuf::from("/a/lib.rs")],
+            None,
+        );
+        assert_eq!(names, vec!["main.rs", "lib.rs"]);
+    }
+
+    #[test]
+    fn test_link_names_collisions() {
+        let targets = [PathBuf::from("/a/main.rs"), PathBuf::from("/b/main.rs")];
+        let names = link_names(&targets, None);
+
+        assert_ne!(names[0], names[1]);
+        assert!(names
+            .iter()
+            .all(|n| n.starts_with("main~") && n.ends_with(".rs")));
+
+        // Same target, same name, no matter what else is in the view
+        let again = link_names(&[targets[1].clone(), PathBuf::from("/c/main.rs")], None);
+        assert_eq!(again[0], names[1]);
+
+        // A short hash clashing with another name falls back to the full one
+        let clash = PathBuf::from("/c").join(&names[0]);
+        let again = link_names(&[targets[0].clone(), targets[1].clone(), clash], None);
+        assert_eq!(again[2], names[0]);
+        assert_eq!(again[1], names[1]);
+        assert!(again[0].starts_with("main~") && again[0].len() == names[0].len() + 10);
+
+        // Nested views keep `by-tag` for the tag folders
+        let names = link_names(&[PathBuf::from("/a/by-tag")], Some(TAG_FOLDERS));
+        assert!(names[0].starts_with("by-tag~"));
+    }
+
+    #[test]
+    fn test_tag_folders() {
+        let tags = vec!["rust".to_string(), "proj".to_string()];
+
+        assert_eq!(
+            tag_folders(&tags, 1),
+            vec![PathBuf::from("proj"), PathBuf::from("rust")]
+        );
+        assert_eq!(
+            tag_folders(&tags, 2),
+            vec![
+                PathBuf::from("proj"),
+                PathBuf::from("rust"),
+                PathBuf::from("proj/rust")
+            ]
+        );
+
+        // Combinations, not every ordering of them
+        let many: Vec<String> = (0..10).map(|i| format!("tag{}", i)).collect();
+        assert_eq!(tag_folders(&many, 2).len(), 10 + 45);
+
+        let lots: Vec<String> = (0..100).map(|i| format!("tag{}", i)).collect();
+        let folders = tag_folders(&lots, 3);
+        assert_eq!(folders.len(), MAX_TAG_FOLDERS);
+        assert!(folders[..100].iter().all(|f| f.components().count() == 1));
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("c/c++"), "c%2Fc++");
+        assert_eq!(sanitize("100%"), "100%25");
+        assert_eq!(sanitize(".."), "%2E%2E");
+        assert_eq!(sanitize("."), "%2E");
+        assert_eq!(sanitize(""), "%00");
+        assert_eq!(sanitize("..."), "...");
+    }
+
+    #[test]
+    fn test_view_stays_inside() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let file = temp_dir.path().join("notes.md");
+        fs::write(&file, "")?;
+        let view = temp_dir.path().join("views").join("notes");
+
+        let mut store = TagStore::in_memory();
+        store.add_tags_batch(std::slice::from_ref(&file), "notes")?;
+        store.add_tags_batch(std::slice::from_ref(&file), "..")?;
+
+        let query = ViewQuery {
+            tags: vec!["notes".into()],
+            nested: Some(2),
+            ..Default::default()
+        };
+        build_view(&store, &view, &query)?;
+
+        assert!(view
+            .join(TAG_FOLDERS)
+            .join("%2E%2E")
+            .join("notes.md")
+            .is_symlink());
+        assert!(!temp_dir.path().join("views").join("notes.md").exists());
+        assert!(!temp_dir.path().join("notes.md").is_symlink());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_view_dirs_skip_symlinks() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let real = temp_dir.path().join("real");
+        let view = temp_dir.path().join("view");
+        fs::create_dir(&real)?;
+        fs::create_dir(&view)?;
+        std::os::unix::fs::symlink(&real, view.join("link"))?;
+
+        assert!(create_view_dirs(&view, &view.join("link").join("inner")).is_err());
+        assert!(!real.join("inner").exists());
+
+        create_view_dirs(&view, &view.join("a").join("b"))?;
+        assert!(view.join("a").join("b").is_dir());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest_roundtrip() -> Result<()> {
+        let query = ViewQuery {
+            tags: vec!["rust".into(), "tag with spaces".into()],
+            exclude: vec!["wip".into()],
+            any: true,
+            nested: Some(2),
+        };
+
+        assert_eq!(ViewQuery::parse(&query.serialize())?, query);
+        Ok(())
+    }
+}
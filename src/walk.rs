@@ -0,0 +1,179 @@
+use std::{path::PathBuf, sync::mpsc, thread};
+
+use ignore::{WalkBuilder, WalkState};
+
+use crate::{Progress, Result};
+
+// Walker threads block once this many entries wait on the consumer,
+// so a slow database writer can't make the walk buffer the whole tree
+const CHANNEL_BOUND: usize = 4096;
+
+/// Lazily yields paths based on the given options (recursive, hidden, threads).
+///
+/// Without `recursive` the paths are yielded as given. With it, every path is
+/// walked respecting `.gitignore`/`.ignore` rules and skipping hidden entries,
+/// unless `hidden` is set. Nothing is buffered, so walking a huge tree costs
+/// the same memory as walking a small one.
+///
+/// `threads` is the number of walker threads, `0` picks one per core. With more
+/// than one the order paths arrive in varies between runs, which paths arrive doesn't.
+pub fn walk_paths(
+    paths: Vec<PathBuf>,
+    recursive: bool,
+    hidden: bool,
+    threads: usize,
+) -> Box<dyn Iterator<Item = PathBuf>> {
+    walk_map(paths, recursive, hidden, threads, &Progress::new(), Some)
+}
+
+/// Like [`walk_paths`], running `map` on the walker threads. Use it to spread
+/// per-path work (stat, reading headers, ...) over the cores too.
+/// Paths `map` returns `None` for are dropped and counted as skipped in `progress`,
+/// same as entries the walker fails to read.
+pub fn walk_map<T, F>(
+    paths: Vec<PathBuf>,
+    recursive: bool,
+    hidden: bool,
+    threads: usize,
+    progress: &Progress,
+    map: F,
+) -> Box<dyn Iterator<Item = T>>
+where
+    T: Send + 'static,
+    F: Fn(PathBuf) -> Option<T> + Send + Sync + 'static,
+{
+    let progress = progress.clone();
+    let map = move |entry: Result<PathBuf, ignore::Error>| {
+        progress.walk();
+        let item = entry.ok().and_then(&map);
+        if item.is_none() {
+            progress.skip();
+        }
+        item
+    };
+
+    // NOTE: Hidden flag only applies for recursive indexing
+    // It doesn't really make sense if someone does ie.
+    // `stag a tag .hidden` and it doesn't index.
+    // Hidden is more for:
+    // `stag a config .config -r --hidden`, which will now recurse
+    // .config and add ALL files no matter ignore-rules
+    if !recursive || paths.is_empty() {
+        return Box::new(paths.into_iter().filter_map(move |path| map(Ok(path))));
+    }
+
+    let mut builder = WalkBuilder::new(&paths[0]);
+    for path in &paths[1..] {
+        builder.add(path);
+    }
+    builder.hidden(!hidden).threads(threads);
+
+    if threads == 1 {
+        return Box::new(
+            builder
+                .build()
+                .filter_map(move |entry| map(entry.map(|e| e.into_path()))),
+        );
+    }
+
+    let (tx, rx) = mpsc::sync_channel(CHANNEL_BOUND);
+
+    let handle = thread::spawn(move || {
+        let map = &map;
+
+        builder.build_parallel().run(|| {
+            let tx = tx.clone();
+            Box::new(move |entry| {
+                let Some(item) = map(entry.map(|e| e.into_path())) else {
+                    return WalkState::Continue;
+                };
+
+                // The receiver is gone (ie. the writer failed), no point walking on
+                match tx.send(item) {
+                    Ok(()) => WalkState::Continue,
+                    Err(_) => WalkState::Quit,
+                }
+            })
+        });
+    });
+
+    Box::new(Walker {
+        rx: rx.into_iter(),
+        handle: Some(handle),
+    })
+}
+
+/// What the walker thread sends. Once that runs dry the thread is joined, so a
+/// panic in it (or in `map`) resurfaces in the consumer instead of looking like
+/// a short walk.
+struct Walker<T> {
+    rx: mpsc::IntoIter<T>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl<T> Iterator for Walker<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let item = self.rx.next();
+
+        if item.is_none() {
+            if let Some(Err(panic)) = self.handle.take().map(|handle| handle.join()) {
+                std::panic::resume_unwind(panic);
+            }
+        }
+
+        item
+    }
+}
+
+/// Collects paths based on the given options (recursive, hidden, threads), see [`walk_paths`].
+pub fn collect_paths(
+    paths: Vec<PathBuf>,
+    recursive: bool,
+    hidden: bool,
+    threads: usize,
+) -> Result<Vec<PathBuf>> {
+    Ok(walk_paths(paths, recursive, hidden, threads).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parallel_walk() -> std::io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        for i in 0..20 {
+            fs::write(temp_dir.path().join(format!("{}.txt", i)), "")?;
+        }
+        let root = vec![temp_dir.path().to_path_buf()];
+
+        let mut paths = collect_paths(root.clone(), true, false, 4).unwrap();
+        paths.sort();
+        let mut single = collect_paths(root.clone(), true, false, 1).unwrap();
+        single.sort();
+        assert_eq!(paths, single);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_walker_panics_resurface() {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let handle = thread::spawn(move || {
+            tx.send(1).unwrap();
+            panic!("walker panic");
+        });
+        let mut walker = Walker {
+            rx: rx.into_iter(),
+            handle: Some(handle),
+        };
+
+        assert_eq!(walker.next(), Some(1));
+        let rest = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| walker.next()));
+        assert!(rest.is_err());
+    }
+}
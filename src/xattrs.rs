@@ -0,0 +1,179 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{collect_paths, Error, Result, TagStore};
+
+/// The freedesktop.org attribute other tools (Dolphin, Baloo, tmsu-fs, ...) read tags from.
+/// Values are a comma separated list, ie. `rust,proj,wip`.
+pub const XDG_TAGS_ATTR: &str = "user.xdg.tags";
+
+/// The tags in `user.xdg.tags` that stag put there, so a push can take back its own
+/// tags without touching the ones other tools wrote.
+const OWNED_ATTR: &str = "user.stag.owned";
+
+// The attribute isn't set, Linux calls it ENODATA and everyone else ENOATTR
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const ENOATTR: i32 = libc::ENODATA;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const ENOATTR: i32 = libc::ENOATTR;
+
+fn describe_error(path: &Path, err: io::Error) -> Error {
+    if err.kind() == io::ErrorKind::Unsupported || err.raw_os_error() == Some(libc::ENOTSUP) {
+        Error::XattrUnsupported(path.to_path_buf())
+    } else {
+        Error::Xattr {
+            path: path.to_path_buf(),
+            source: err,
+        }
+    }
+}
+
+/// Reads the tags stored in `user.xdg.tags`, an empty list if the attribute is not set.
+pub fn read_tags(path: &Path) -> Result<Vec<String>> {
+    read_list(path, XDG_TAGS_ATTR)
+}
+
+/// Writes `tags` into `user.xdg.tags`, removing the attribute entirely if there are none.
+pub fn write_tags(path: &Path, tags: &[String]) -> Result<()> {
+    write_list(path, XDG_TAGS_ATTR, tags)
+}
+
+fn read_list(path: &Path, attr: &str) -> Result<Vec<String>> {
+    let value = xattr::get(path, attr).map_err(|e| describe_error(path, e))?;
+
+    Ok(value
+        .map(|bytes| {
+            String::from_utf8_lossy(&bytes)
+                .split(',')
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+fn write_list(path: &Path, attr: &str, tags: &[String]) -> Result<()> {
+    if tags.is_empty() {
+        return match xattr::remove(path, attr) {
+            // Removing an attribute that isn't there is fine
+            Err(err) if err.raw_os_error() == Some(ENOATTR) => Ok(()),
+            other => other.map_err(|e| describe_error(path, e)),
+        };
+    }
+
+    xattr::set(path, attr, tags.join(",").as_bytes()).map_err(|e| describe_error(path, e))
+}
+
+/// Adds or removes a single tag from the attribute, leaving tags from other tools alone.
+/// This is what `add --xattr` and `rm --xattr` use to keep both sides in sync.
+pub fn update_tag(path: &Path, tag: &str, add: bool) -> Result<()> {
+    let mut tags = read_tags(path)?;
+    let present = tags.iter().any(|t| t == tag);
+
+    let mut owned = read_list(path, OWNED_ATTR)?;
+
+    match (add, present) {
+        (true, false) => {
+            tags.push(tag.to_string());
+            owned.push(tag.to_string());
+        }
+        (false, true) => {
+            tags.retain(|t| t != tag);
+            owned.retain(|t| t != tag);
+        }
+        _ => return Ok(()),
+    }
+
+    write_tags(path, &tags)?;
+    write_list(path, OWNED_ATTR, &owned)
+}
+
+/// Paths that couldn't be synced, with why. One file on a filesystem without
+/// xattrs shouldn't stop a recursive sync, so failures are collected instead.
+pub type SyncFailures = Vec<(PathBuf, Error)>;
+
+/// Merges the tags stag has stored for each path into `user.xdg.tags`. Tags stag
+/// pushed before but no longer has are taken out again, other tools' tags stay.
+pub fn push_paths(
+    store: &TagStore,
+    paths: Vec<PathBuf>,
+    recursive: bool,
+    hidden: bool,
+    threads: usize,
+) -> Result<SyncFailures> {
+    let paths = collect_paths(paths, recursive, hidden, threads)?;
+
+    Ok(sync_each(&paths, |path| {
+        let stored = store.get_file_tags(path)?;
+        let previous = read_list(path, OWNED_ATTR)?;
+        let mut tags = read_tags(path)?;
+
+        // Tags that were already there before stag pushed them aren't stag's to remove
+        let owned: Vec<String> = stored
+            .iter()
+            .filter(|tag| previous.contains(tag) || !tags.contains(tag))
+            .cloned()
+            .collect();
+
+        tags.retain(|tag| stored.contains(tag) || !previous.contains(tag));
+        for tag in &stored {
+            if !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
+        }
+
+        write_tags(path, &tags)?;
+        write_list(path, OWNED_ATTR, &owned)
+    }))
+}
+
+/// Adds every tag found in `user.xdg.tags` to the store.
+pub fn pull_paths(
+    store: &mut TagStore,
+    paths: Vec<PathBuf>,
+    recursive: bool,
+    hidden: bool,
+    threads: usize,
+) -> Result<SyncFailures> {
+    let paths = collect_paths(paths, recursive, hidden, threads)?;
+
+    Ok(sync_each(&paths, |path| {
+        for tag in read_tags(path)? {
+            store.add_tags_batch(&[path.to_path_buf()], &tag)?;
+        }
+        Ok(())
+    }))
+}
+
+/// Runs `sync` for every path, collecting failures instead of bailing on the first one.
+pub fn sync_each<F>(paths: &[PathBuf], mut sync: F) -> SyncFailures
+where
+    F: FnMut(&Path) -> Result<()>,
+{
+    paths
+        .iter()
+        .filter_map(|path| sync(path).err().map(|err| (path.clone(), err)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_error() {
+        let path = Path::new("/mnt/vfat/file");
+
+        let err = describe_error(path, io::Error::from_raw_os_error(libc::ENOTSUP));
+        assert!(matches!(err, Error::XattrUnsupported(p) if p == path));
+
+        let err = describe_error(path, io::ErrorKind::Unsupported.into());
+        assert!(matches!(err, Error::XattrUnsupported(_)));
+
+        let err = describe_error(path, io::Error::from_raw_os_error(libc::EACCES));
+        assert!(matches!(err, Error::Xattr { .. }));
+    }
+}
@@ -0,0 +1,107 @@
+use stag::{autotag, Error, Progress, Query, RuleSet, TagStore};
+use tempfile::TempDir;
+
+#[test]
+fn test_open_store_on_disk() -> stag::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let db_path = temp_dir.path().join("tags.db");
+    let test_file = temp_dir.path().join("test.txt");
+    std::fs::write(&test_file, "content")?;
+
+    {
+        let mut store = TagStore::open(&db_path)?;
+        store.add_tags_batch(std::slice::from_ref(&test_file), "rust")?;
+    }
+
+    // Reopening sees what the first handle wrote
+    let store = TagStore::open(&db_path)?;
+    assert_eq!(store.get_file_tags(&test_file)?, vec!["rust"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_query_builder() -> stag::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut store = TagStore::open_in_memory()?;
+
+    let rust = temp_dir.path().join("rust");
+    let python = temp_dir.path().join("python");
+    std::fs::create_dir(&rust)?;
+    std::fs::create_dir(&python)?;
+
+    store.add_tags_batch(&[rust.clone(), python.clone()], "proj")?;
+    store.add_tags_batch(std::slice::from_ref(&rust), "rust")?;
+
+    let results = store.search(&Query::new().tag("proj").exclude("rust"))?;
+    assert_eq!(results, vec![python.canonicalize()?]);
+
+    let results = store.search(&Query::new().tags(["rust", "missing"]).any(true))?;
+    assert_eq!(results, vec![rust.canonicalize()?]);
+
+    Ok(())
+}
+
+#[test]
+fn test_typed_errors() -> stag::Result<()> {
+    let mut store = TagStore::open_in_memory()?;
+
+    let err = store
+        .add_tags_batch(&["/definitely/not/a/real/path".into()], "tag")
+        .unwrap_err();
+    assert!(matches!(err, Error::PathNotFound(_)));
+
+    // Lookups fall back to the stored path string, a missing path just has no tags
+    assert!(store
+        .get_file_tags("/definitely/not/a/real/path".as_ref())?
+        .is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_autotag_through_api() -> stag::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let readme = temp_dir.path().join("README.md");
+    std::fs::write(&readme, "# hi")?;
+
+    let tags = autotag::generate_tags(&readme)?;
+    assert!(tags.contains(&"file".to_string()));
+    assert!(tags.contains(&"small".to_string()));
+
+    // No database needed, the autotagger runs against any backend
+    let mut store = TagStore::in_memory();
+    let progress = Progress::new();
+    let changes = autotag::autotag_paths(
+        &mut store,
+        vec![temp_dir.path().to_path_buf()],
+        true,
+        false,
+        0,
+        &RuleSet::default(),
+        &progress,
+    )?;
+
+    assert!(changes.changed > 0);
+    assert_eq!(progress.skipped(), 0);
+
+    assert!(store
+        .search(&Query::new().tag("file"))?
+        .contains(&readme.canonicalize()?));
+
+    Ok(())
+}
+
+#[test]
+fn test_store_uses_wal() -> stag::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let db_path = temp_dir.path().join("tags.db");
+    let _store = TagStore::open(&db_path)?;
+
+    // WAL is persistent, any other connection sees it
+    let conn = rusqlite::Connection::open(&db_path)?;
+    let mode: String = conn.query_row("PRAGMA journal_mode", [], |row| row.get(0))?;
+    assert_eq!(mode, "wal");
+
+    Ok(())
+}
@@ -469,3 +469,1368 @@ fn test_autotag() -> Result<()> {
         Ok(())
     })
 }
+
+fn xattr_supported(path: &std::path::Path) -> bool {
+    xattr::set(path, "user.stag.probe", b"1").is_ok()
+}
+
+#[test]
+#[serial]
+fn test_xattr_push_and_pull() -> Result<()> {
+    with_test_env(|| {
+        let temp_dir = TempDir::new()?;
+        let pushed = temp_dir.path().join("pushed.txt");
+        let pulled = temp_dir.path().join("pulled.txt");
+        std::fs::write(&pushed, "content")?;
+        std::fs::write(&pulled, "content")?;
+
+        if !xattr_supported(&pushed) {
+            eprintln!("skipping, no user xattr support in temp dir");
+            return Ok(());
+        }
+
+        let pushed_path = normalize_path(&pushed)?;
+        let pulled_path = normalize_path(&pulled)?;
+
+        Command::cargo_bin("stag")?
+            .args(["a", "rust", &pushed_path])
+            .assert()
+            .success();
+
+        Command::cargo_bin("stag")?
+            .args(["xattr", "push", &pushed_path])
+            .assert()
+            .success();
+
+        assert_eq!(
+            xattr::get(&pushed, "user.xdg.tags")?,
+            Some(b"rust".to_vec())
+        );
+
+        // Other tools' tags survive a push, stag only takes back its own
+        xattr::set(&pushed, "user.xdg.tags", b"rust,foreign")?;
+        Command::cargo_bin("stag")?
+            .args(["rm", "rust", &pushed_path])
+            .assert()
+            .success();
+        Command::cargo_bin("stag")?
+            .args(["xattr", "push", &pushed_path])
+            .assert()
+            .success();
+
+        assert_eq!(
+            xattr::get(&pushed, "user.xdg.tags")?,
+            Some(b"foreign".to_vec())
+        );
+
+        xattr::set(&pulled, "user.xdg.tags", b"music, favourite")?;
+
+        Command::cargo_bin("stag")?
+            .args(["xattr", "pull", &pulled_path])
+            .assert()
+            .success();
+
+        Command::cargo_bin("stag")?
+            .args(["s", "music", "favourite"])
+            .assert()
+            .success()
+            .stdout(predicates::str::contains(&pulled_path));
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_xattr_sync_on_add_and_remove() -> Result<()> {
+    with_test_env(|| {
+        let temp_dir = TempDir::new()?;
+        let test_file = temp_dir.path().join("test.txt");
+        std::fs::write(&test_file, "content")?;
+
+        if !xattr_supported(&test_file) {
+            eprintln!("skipping, no user xattr support in temp dir");
+            return Ok(());
+        }
+
+        // Tags set by other tools should survive the sync
+        xattr::set(&test_file, "user.xdg.tags", b"foreign")?;
+        let normalized_path = normalize_path(&test_file)?;
+
+        Command::cargo_bin("stag")?
+            .args(["a", "rust", &normalized_path, "--xattr"])
+            .assert()
+            .success();
+
+        assert_eq!(
+            xattr::get(&test_file, "user.xdg.tags")?,
+            Some(b"foreign,rust".to_vec())
+        );
+
+        Command::cargo_bin("stag")?
+            .args(["rm", "rust", &normalized_path])
+            .env("STAG_XATTR_SYNC", "1")
+            .assert()
+            .success();
+
+        assert_eq!(
+            xattr::get(&test_file, "user.xdg.tags")?,
+            Some(b"foreign".to_vec())
+        );
+
+        // Same for everything below a directory
+        let dir = normalize_path(temp_dir.path())?;
+        Command::cargo_bin("stag")?
+            .args(["a", "wip", &dir, "-r", "--xattr"])
+            .assert()
+            .success();
+        assert_eq!(
+            xattr::get(&test_file, "user.xdg.tags")?,
+            Some(b"foreign,wip".to_vec())
+        );
+
+        Command::cargo_bin("stag")?
+            .args(["rm", "wip", "--under", &dir, "--xattr"])
+            .assert()
+            .success();
+        assert_eq!(
+            xattr::get(&test_file, "user.xdg.tags")?,
+            Some(b"foreign".to_vec())
+        );
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_import_tmsu() -> Result<()> {
+    with_test_env(|| {
+        let temp_dir = TempDir::new()?;
+        let song = temp_dir.path().join("song.flac");
+        std::fs::write(&song, "content")?;
+        let song_path = normalize_path(&song)?;
+        let song_dir = normalize_path(temp_dir.path())?;
+
+        let tmsu_db = temp_dir.path().join("tmsu.db");
+        let conn = rusqlite::Connection::open(&tmsu_db)?;
+        conn.execute_batch(&format!(
+            "CREATE TABLE tag (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+             CREATE TABLE value (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+             CREATE TABLE file (id INTEGER PRIMARY KEY, directory TEXT NOT NULL, name TEXT NOT NULL,
+                                fingerprint TEXT NOT NULL, mod_time DATETIME NOT NULL,
+                                size INTEGER NOT NULL, is_dir BOOLEAN NOT NULL);
+             CREATE TABLE file_tag (file_id INTEGER NOT NULL, tag_id INTEGER NOT NULL,
+                                    value_id INTEGER NOT NULL);
+             INSERT INTO tag VALUES (1, 'music'), (2, 'year');
+             INSERT INTO value VALUES (1, '2017');
+             INSERT INTO file VALUES (1, '{song_dir}', 'song.flac', '', '', 7, 0);
+             INSERT INTO file VALUES (2, '{song_dir}', 'gone.flac', '', '', 7, 0);
+             INSERT INTO file_tag VALUES (1, 1, 0), (1, 2, 1), (2, 1, 0);"
+        ))?;
+        drop(conn);
+
+        Command::cargo_bin("stag")?
+            .args(["import", "--from", "tmsu", &tmsu_db.to_string_lossy()])
+            .assert()
+            .success()
+            .stderr(predicates::str::contains("gone.flac"));
+
+        Command::cargo_bin("stag")?
+            .args(["s", "music", "year=2017"])
+            .assert()
+            .success()
+            .stdout(predicates::str::contains(&song_path));
+
+        // Already imported pairs don't count again
+        Command::cargo_bin("stag")?
+            .args(["import", "--from", "tmsu", &tmsu_db.to_string_lossy()])
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("Imported 0 tag association(s)"));
+
+        // Relative paths need to know the root when the db isn't in `.tmsu`
+        let conn = rusqlite::Connection::open(&tmsu_db)?;
+        conn.execute_batch(
+            "INSERT INTO tag VALUES (3, 'local');
+             INSERT INTO file VALUES (3, '.', 'song.flac', '', '', 7, 0);
+             INSERT INTO file_tag VALUES (3, 3, 0);",
+        )?;
+        drop(conn);
+
+        Command::cargo_bin("stag")?
+            .args(["import", "--from", "tmsu", &tmsu_db.to_string_lossy()])
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("--root"));
+
+        Command::cargo_bin("stag")?
+            .args(["import", "--from", "tmsu", &tmsu_db.to_string_lossy()])
+            .args(["--root", &song_dir])
+            .assert()
+            .success();
+
+        Command::cargo_bin("stag")?
+            .args(["s", "local"])
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("song.flac"));
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_view_and_refresh() -> Result<()> {
+    with_test_env(|| {
+        let temp_dir = TempDir::new()?;
+        let first = temp_dir.path().join("a");
+        let second = temp_dir.path().join("b");
+        std::fs::create_dir(&first)?;
+        std::fs::create_dir(&second)?;
+        std::fs::write(first.join("main.rs"), "fn main() {}")?;
+        std::fs::write(second.join("main.rs"), "fn main() {}")?;
+
+        let first_path = normalize_path(&first.join("main.rs"))?;
+        let second_path = normalize_path(&second.join("main.rs"))?;
+        let view_dir = temp_dir.path().join("view");
+
+        Command::cargo_bin("stag")?
+            .args(["a", "rust", &first_path, &second_path])
+            .assert()
+            .success();
+
+        Command::cargo_bin("stag")?
+            .args(["view", &view_dir.to_string_lossy(), "rust"])
+            .assert()
+            .success();
+
+        // Identical basenames get distinct names
+        let links = |dir: &std::path::Path| -> Result<Vec<std::path::PathBuf>> {
+            let mut links = Vec::new();
+            for entry in std::fs::read_dir(dir)? {
+                let entry = entry?;
+                if entry.file_type()?.is_symlink() {
+                    links.push(std::fs::read_link(entry.path())?);
+                }
+            }
+            Ok(links)
+        };
+        assert_eq!(links(&view_dir)?.len(), 2);
+
+        Command::cargo_bin("stag")?
+            .args(["rm", "rust", &second_path])
+            .assert()
+            .success();
+
+        Command::cargo_bin("stag")?
+            .args(["view", &view_dir.to_string_lossy(), "--refresh"])
+            .assert()
+            .success();
+
+        assert_eq!(
+            links(&view_dir)?,
+            vec![std::path::PathBuf::from(&first_path)]
+        );
+        assert!(view_dir.join("main.rs").is_symlink());
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_nested_view_tag_named_like_result() -> Result<()> {
+    with_test_env(|| {
+        let temp_dir = TempDir::new()?;
+        let proj = temp_dir.path().join("src").join("proj");
+        std::fs::create_dir_all(&proj)?;
+        let proj_path = normalize_path(&proj)?;
+        let view_dir = temp_dir.path().join("view");
+
+        Command::cargo_bin("stag")?
+            .args(["a", "rust", &proj_path])
+            .assert()
+            .success();
+        Command::cargo_bin("stag")?
+            .args(["a", "proj", &proj_path])
+            .assert()
+            .success();
+
+        Command::cargo_bin("stag")?
+            .args(["view", &view_dir.to_string_lossy(), "rust", "--nested"])
+            .assert()
+            .success();
+
+        assert!(view_dir.join("proj").is_symlink());
+        assert!(view_dir.join("by-tag/proj/proj").is_symlink());
+        // Nothing written through the link into the real directory
+        assert_eq!(std::fs::read_dir(&proj)?.count(), 0);
+
+        Command::cargo_bin("stag")?
+            .args(["view", &view_dir.to_string_lossy(), "--refresh"])
+            .assert()
+            .success();
+        assert!(view_dir.join("by-tag/proj/proj").is_symlink());
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_parallel_writers() -> Result<()> {
+    with_test_env(|| {
+        let temp_dir = TempDir::new()?;
+        let dirs: Vec<String> = (0..16)
+            .map(|i| {
+                let dir = temp_dir.path().join(format!("dir{}", i));
+                std::fs::create_dir(&dir)?;
+                for j in 0..100 {
+                    std::fs::write(dir.join(format!("file{}", j)), "content")?;
+                }
+                normalize_path(&dir)
+            })
+            .collect::<Result<_>>()?;
+
+        // Two writers per directory, one with its own tag and one with a shared tag,
+        // plus a reader, all started before any of them is waited on
+        let spawn = |args: &[&str]| {
+            std::process::Command::new(assert_cmd::cargo::cargo_bin("stag"))
+                .args(args)
+                .stdout(std::process::Stdio::null())
+                .spawn()
+        };
+
+        let mut children = Vec::new();
+        for (i, dir) in dirs.iter().enumerate() {
+            children.push(spawn(&["a", &format!("tag{}", i), dir, "-r"])?);
+            children.push(spawn(&["a", "shared", dir, "-r"])?);
+            children.push(spawn(&["ls", "shared"])?);
+        }
+
+        for mut child in children {
+            assert!(child.wait()?.success());
+        }
+
+        // No lost writes, 100 files and the directory itself for every writer
+        for i in 0..dirs.len() {
+            let tagged = Command::cargo_bin("stag")?
+                .args(["ls", &format!("tag{}", i)])
+                .output()?;
+            assert_eq!(String::from_utf8(tagged.stdout)?.lines().count(), 101);
+        }
+
+        let shared = Command::cargo_bin("stag")?
+            .args(["ls", "shared"])
+            .output()?;
+        assert_eq!(
+            String::from_utf8(shared.stdout)?.lines().count(),
+            dirs.len() * 101
+        );
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_parallel_walk_is_deterministic() -> Result<()> {
+    with_test_env(|| {
+        let temp_dir = TempDir::new()?;
+        for i in 0..8 {
+            let dir = temp_dir.path().join(format!("dir{}", i));
+            std::fs::create_dir(&dir)?;
+            std::fs::write(dir.join("file.txt"), "content")?;
+            std::fs::write(dir.join(".hidden"), "content")?;
+        }
+        std::fs::write(temp_dir.path().join(".ignore"), "dir3/\n")?;
+
+        let root = normalize_path(temp_dir.path())?;
+
+        Command::cargo_bin("stag")?
+            .args(["a", "serial", &root, "-r", "-j", "1"])
+            .assert()
+            .success();
+
+        Command::cargo_bin("stag")?
+            .args(["a", "parallel", &root, "-r", "-j", "4"])
+            .assert()
+            .success();
+
+        let sorted = |tag: &str| -> Result<Vec<String>> {
+            let output = Command::cargo_bin("stag")?.args(["ls", tag]).output()?;
+            let mut lines: Vec<String> = String::from_utf8(output.stdout)?
+                .lines()
+                .map(str::to_string)
+                .collect();
+            lines.sort();
+            Ok(lines)
+        };
+
+        let serial = sorted("serial")?;
+        assert_eq!(serial, sorted("parallel")?);
+        // Root + 7 dirs + 7 files, ignore rules and hidden files apply the same way
+        assert_eq!(serial.len(), 15);
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_summary_and_quiet() -> Result<()> {
+    with_test_env(|| {
+        let temp_dir = TempDir::new()?;
+        for name in ["a.txt", "b.txt"] {
+            std::fs::write(temp_dir.path().join(name), "test")?;
+        }
+        let dir = normalize_path(temp_dir.path())?;
+
+        Command::cargo_bin("stag")?
+            .args(["a", "notes", &dir, "-r"])
+            .assert()
+            .success()
+            .stderr(predicate::str::contains(
+                "Added 3 tag(s), 0 already tagged, 0 skipped",
+            ));
+
+        Command::cargo_bin("stag")?
+            .args(["a", "notes", &dir, "-r"])
+            .assert()
+            .success()
+            .stderr(predicate::str::contains(
+                "Added 0 tag(s), 3 already tagged, 0 skipped",
+            ));
+
+        Command::cargo_bin("stag")?
+            .args(["rm", "notes", &dir, "-r", "--quiet"])
+            .assert()
+            .success()
+            .stderr(predicate::str::is_empty());
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_dry_run() -> Result<()> {
+    with_test_env(|| {
+        let temp_dir = TempDir::new()?;
+        let tagged = temp_dir.path().join("tagged.txt");
+        let untagged = temp_dir.path().join("untagged.txt");
+        std::fs::write(&tagged, "test")?;
+        std::fs::write(&untagged, "test")?;
+
+        let norm_tagged = normalize_path(&tagged)?;
+        let norm_untagged = normalize_path(&untagged)?;
+        let dir = normalize_path(temp_dir.path())?;
+
+        Command::cargo_bin("stag")?
+            .args(["a", "rust", &norm_tagged])
+            .assert()
+            .success();
+
+        Command::cargo_bin("stag")?
+            .args(["a", "rust", &dir, "-r", "--dry-run"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(format!("+rust {}", norm_untagged)))
+            .stdout(predicate::str::contains(format!("+rust {}\n", dir)))
+            .stdout(predicate::str::contains(&norm_tagged).not());
+
+        Command::cargo_bin("stag")?
+            .args(["rm", "rust", &dir, "-r", "-n"])
+            .assert()
+            .success()
+            .stdout(format!("-rust {}\n", norm_tagged));
+
+        // Nothing changed
+        Command::cargo_bin("stag")?
+            .args(["ls", "rust"])
+            .assert()
+            .success()
+            .stdout(format!("{}\n", norm_tagged));
+
+        Command::cargo_bin("stag")?
+            .args(["at", &norm_untagged, "--dry-run"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(format!("+file {}", norm_untagged)))
+            .stdout(predicate::str::contains(format!(
+                "+mime:text/plain {}",
+                norm_untagged
+            )));
+
+        // --preview still works
+        Command::cargo_bin("stag")?
+            .args(["at", &norm_untagged, "--preview"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(format!("+file {}", norm_untagged)));
+
+        Command::cargo_bin("stag")?
+            .args(["s", "file"])
+            .assert()
+            .success()
+            .stdout(predicate::str::is_empty());
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_untag() -> Result<()> {
+    with_test_env(|| {
+        let temp_dir = TempDir::new()?;
+        let project = temp_dir.path().join("project");
+        let file = project.join("main.rs");
+        std::fs::create_dir(&project)?;
+        std::fs::write(&file, "fn main() {}")?;
+
+        let norm_project = normalize_path(&project)?;
+        let norm_file = normalize_path(&file)?;
+
+        for tag in ["rust", "wip"] {
+            Command::cargo_bin("stag")?
+                .args(["a", tag, &norm_project, "-r"])
+                .assert()
+                .success();
+        }
+
+        Command::cargo_bin("stag")?
+            .args(["untag", &norm_file])
+            .assert()
+            .success()
+            .stderr("Removed 2 tag(s)\n");
+
+        Command::cargo_bin("stag")?
+            .args(["s", "rust"])
+            .assert()
+            .success()
+            .stdout(format!("{}\n", norm_project));
+
+        // Works after the directory is gone
+        std::fs::remove_dir_all(&project)?;
+
+        Command::cargo_bin("stag")?
+            .args(["untag", &norm_project, "-r", "--forget"])
+            .assert()
+            .success()
+            .stderr("Removed 2 tag(s)\n");
+
+        Command::cargo_bin("stag")?
+            .args(["s", "rust", "wip", "--any"])
+            .assert()
+            .success()
+            .stdout(predicate::str::is_empty());
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_remove_deleted_path() -> Result<()> {
+    with_test_env(|| {
+        let temp_dir = TempDir::new()?;
+        let test_file = temp_dir.path().join("gone.txt");
+        std::fs::write(&test_file, "test")?;
+        let norm_path = normalize_path(&test_file)?;
+
+        for tag in ["keep", "stale"] {
+            Command::cargo_bin("stag")?
+                .args(["a", tag, &norm_path])
+                .assert()
+                .success();
+        }
+
+        std::fs::remove_file(&test_file)?;
+
+        Command::cargo_bin("stag")?
+            .args(["rm", "stale", &norm_path])
+            .assert()
+            .success()
+            .stderr(predicate::str::contains("Removed 1 tag(s)"));
+
+        Command::cargo_bin("stag")?
+            .args(["i", &norm_path])
+            .assert()
+            .success()
+            .stdout("keep\n");
+
+        Command::cargo_bin("stag")?
+            .args([
+                "i",
+                &format!("{}/never.txt", normalize_path(temp_dir.path())?),
+            ])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("Path does not exist"));
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_under() -> Result<()> {
+    with_test_env(|| {
+        let temp_dir = TempDir::new()?;
+        let old = temp_dir.path().join("old-project");
+        let work = temp_dir.path().join("work");
+        std::fs::create_dir(&old)?;
+        std::fs::create_dir(&work)?;
+        std::fs::write(old.join("notes.md"), "notes")?;
+        std::fs::write(work.join("todo.md"), "todo")?;
+
+        let norm_old = normalize_path(&old)?;
+        let norm_notes = normalize_path(&old.join("notes.md"))?;
+        let norm_work = normalize_path(&work)?;
+        let norm_todo = normalize_path(&work.join("todo.md"))?;
+
+        Command::cargo_bin("stag")?
+            .args(["a", "wip", &normalize_path(temp_dir.path())?, "-r"])
+            .assert()
+            .success();
+
+        Command::cargo_bin("stag")?
+            .args(["ls", "wip", "--under", &norm_work, "--files"])
+            .assert()
+            .success()
+            .stdout(format!("{}\n", norm_todo));
+
+        // Gone from disk, still reachable through the stored paths
+        std::fs::remove_dir_all(&old)?;
+
+        Command::cargo_bin("stag")?
+            .args(["s", "wip", "--under", &norm_old])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(&norm_notes))
+            .stdout(predicate::str::contains(&norm_todo).not());
+
+        Command::cargo_bin("stag")?
+            .args(["rm", "wip", "--under", &norm_old, "--dry-run"])
+            .assert()
+            .success()
+            .stdout(format!("-wip {}\n-wip {}\n", norm_old, norm_notes));
+
+        // Nothing gets walked, so walk flags make no sense
+        Command::cargo_bin("stag")?
+            .args(["rm", "wip", "--under", &norm_old, "-r"])
+            .assert()
+            .failure();
+
+        Command::cargo_bin("stag")?
+            .args(["rm", "wip", "--under", &norm_old])
+            .assert()
+            .success()
+            .stderr(predicate::str::contains("Removed 2 tag(s)"));
+
+        Command::cargo_bin("stag")?
+            .args(["untag", "--under", &norm_work])
+            .assert()
+            .success()
+            .stderr("Removed 2 tag(s)\n");
+
+        Command::cargo_bin("stag")?
+            .args(["ls", "wip"])
+            .assert()
+            .success()
+            .stdout(format!("{}\n", normalize_path(temp_dir.path())?));
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_relocate() -> Result<()> {
+    with_test_env(|| {
+        let temp_dir = TempDir::new()?;
+        let projects = temp_dir.path().join("Projects");
+        let src = temp_dir.path().join("src");
+        std::fs::create_dir_all(projects.join("stag"))?;
+        std::fs::create_dir_all(src.join("stag"))?;
+
+        let norm_projects = normalize_path(&projects)?;
+        let norm_src = normalize_path(&src)?;
+
+        Command::cargo_bin("stag")?
+            .args(["a", "rust", &norm_projects, "-r"])
+            .assert()
+            .success();
+        Command::cargo_bin("stag")?
+            .args(["a", "cli", &normalize_path(&src.join("stag"))?])
+            .assert()
+            .success();
+
+        // Move it over the existing tree, the entries for src/stag get merged
+        std::fs::remove_dir_all(&src)?;
+        std::fs::rename(&projects, &src)?;
+
+        Command::cargo_bin("stag")?
+            .args(["relocate", &norm_projects, &norm_src])
+            .assert()
+            .success()
+            .stdout("Relocated 2 path(s), 1 merged into existing entries\n");
+
+        Command::cargo_bin("stag")?
+            .args(["s", "rust", "cli"])
+            .assert()
+            .success()
+            .stdout(format!("{}/stag\n", norm_src));
+
+        Command::cargo_bin("stag")?
+            .args(["ls", "rust", "--under", &norm_projects])
+            .assert()
+            .success()
+            .stdout(predicate::str::is_empty());
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_portable_store() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let drive = temp_dir.path().join("drive");
+    let photos = drive.join("photos");
+    std::fs::create_dir_all(&photos)?;
+    std::fs::write(photos.join("cat.jpg"), "meow")?;
+
+    // Not through with_test_env, the store has to be discovered
+    let stag = |dir: &std::path::Path| -> Result<Command> {
+        let mut cmd = Command::cargo_bin("stag")?;
+        cmd.env_remove("STAG_DB_PATH").current_dir(dir);
+        Ok(cmd)
+    };
+
+    stag(&drive)?
+        .args(["init"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(".stag"));
+
+    stag(&photos)?
+        .args(["a", "cats", "cat.jpg"])
+        .assert()
+        .success();
+
+    // Mounted somewhere else
+    let mounted = temp_dir.path().join("mnt");
+    std::fs::rename(&drive, &mounted)?;
+    let norm_cat = normalize_path(&mounted.join("photos/cat.jpg"))?;
+
+    stag(&mounted.join("photos"))?
+        .args(["ls", "cats"])
+        .assert()
+        .success()
+        .stdout(format!("{}\n", norm_cat));
+
+    stag(&mounted)?
+        .args(["a", "cats", temp_dir.path().to_str().unwrap()])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("outside the portable store"));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_named_stores() -> Result<()> {
+    let home = TempDir::new()?;
+    let temp_dir = TempDir::new()?;
+    let report = temp_dir.path().join("report.md");
+    let diary = temp_dir.path().join("diary.md");
+    std::fs::write(&report, "q3")?;
+    std::fs::write(&diary, "dear diary")?;
+
+    let norm_report = normalize_path(&report)?;
+    let norm_diary = normalize_path(&diary)?;
+
+    // Named stores live in the data dir, keep it away from the real one
+    let stag = || -> Result<Command> {
+        let mut cmd = Command::cargo_bin("stag")?;
+        cmd.env_remove("STAG_DB_PATH")
+            .env_remove("STAG_STORE")
+            .env("XDG_DATA_HOME", home.path().join("data"))
+            .env("XDG_CONFIG_HOME", home.path().join("config"));
+        Ok(cmd)
+    };
+
+    stag()?
+        .args(["a", "notes", &norm_report, "--store", "work"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("stag store create work"));
+
+    stag()?.args(["store", "create", "work"]).assert().success();
+
+    stag()?
+        .args(["--store", "work", "a", "notes", &norm_report])
+        .assert()
+        .success();
+    stag()?.args(["a", "notes", &norm_diary]).assert().success();
+
+    stag()?
+        .args(["s", "notes"])
+        .assert()
+        .success()
+        .stdout(format!("{}\n", norm_diary));
+
+    stag()?
+        .args(["s", "notes", "--all-stores"])
+        .assert()
+        .success()
+        .stdout(format!("default\t{}\nwork\t{}\n", norm_diary, norm_report));
+
+    let output = stag()?
+        .args(["s", "notes", "--stores", "work", "--json"])
+        .output()?;
+    assert!(output.status.success());
+    let hits: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(
+        hits,
+        serde_json::json!([{ "path": norm_report, "stores": ["work"] }])
+    );
+
+    stag()?
+        .args(["s", "notes", "--all-stores", "--federated"])
+        .assert()
+        .failure();
+
+    // Picked in the config instead
+    std::fs::create_dir_all(home.path().join("config/stag"))?;
+    std::fs::write(
+        home.path().join("config/stag/config.toml"),
+        "store = \"work\"\n",
+    )?;
+
+    stag()?
+        .args(["ls", "notes"])
+        .assert()
+        .success()
+        .stdout(format!("{}\n", norm_report));
+
+    stag()?
+        .args(["store", "list"])
+        .assert()
+        .success()
+        .stdout("  default\n* work\n");
+
+    stag()?
+        .args(["store", "delete", "default"])
+        .assert()
+        .failure();
+
+    stag()?.args(["store", "delete", "work"]).assert().success();
+
+    stag()?
+        .args(["store", "list", "--store", "default"])
+        .assert()
+        .success()
+        .stdout("* default\n");
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_federated_search() -> Result<()> {
+    with_test_env(|| {
+        let temp_dir = TempDir::new()?;
+        let project = temp_dir.path().join("project");
+        std::fs::create_dir(&project)?;
+        std::fs::write(project.join("main.rs"), "fn main() {}")?;
+        std::fs::write(project.join("lib.rs"), "")?;
+
+        let norm_project = normalize_path(&project)?;
+        let norm_main = normalize_path(&project.join("main.rs"))?;
+        let norm_lib = normalize_path(&project.join("lib.rs"))?;
+        let db_path = std::env::var("STAG_DB_PATH")?;
+
+        // A configured project without a store of its own
+        let unstored = temp_dir.path().join("unstored");
+        std::fs::create_dir(&unstored)?;
+        let config = temp_dir.path().join("config");
+        std::fs::create_dir_all(config.join("stag"))?;
+        std::fs::write(
+            config.join("stag/config.toml"),
+            format!("projects = [{:?}]\n", normalize_path(&unstored)?),
+        )?;
+
+        Command::cargo_bin("stag")?
+            .args(["init", &norm_project])
+            .assert()
+            .success();
+
+        // Global store, STAG_DB_PATH from the test env
+        Command::cargo_bin("stag")?
+            .args(["a", "rust", &norm_main])
+            .assert()
+            .success();
+
+        // Project store, found from the working directory
+        for (tag, file) in [("rust", "main.rs"), ("rust", "lib.rs"), ("wip", "main.rs")] {
+            Command::cargo_bin("stag")?
+                .env_remove("STAG_DB_PATH")
+                .current_dir(&project)
+                .args(["a", tag, file])
+                .assert()
+                .success();
+        }
+
+        Command::cargo_bin("stag")?
+            .current_dir(&project)
+            .args(["s", "rust"])
+            .assert()
+            .success()
+            .stdout(format!("{}\n", norm_main));
+
+        Command::cargo_bin("stag")?
+            .current_dir(&project)
+            .env("XDG_CONFIG_HOME", &config)
+            .args(["s", "rust", "--federated"])
+            .assert()
+            .success()
+            .stdout(format!("{}\n{}\n", norm_lib, norm_main));
+        // Searching doesn't create stores
+        assert!(!unstored.join(".stag").exists());
+
+        let output = Command::cargo_bin("stag")?
+            .current_dir(&project)
+            .args(["i", "main.rs", "--federated", "--json"])
+            .output()?;
+        assert!(output.status.success());
+
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "path": norm_main,
+                "tags": [
+                    { "tag": "rust", "stores": [db_path, norm_project] },
+                    { "tag": "wip", "stores": [norm_project] },
+                ],
+            }])
+        );
+
+        // Without --federated only the global store
+        Command::cargo_bin("stag")?
+            .current_dir(&project)
+            .args(["ls", "wip", "--json"])
+            .assert()
+            .success()
+            .stdout("[]\n");
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_sidecar() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let repo = temp_dir.path().join("repo");
+    std::fs::create_dir_all(repo.join("src"))?;
+    std::fs::write(repo.join("src/main.rs"), "fn main() {}")?;
+
+    let norm_repo = normalize_path(&repo)?;
+    let norm_main = normalize_path(&repo.join("src/main.rs"))?;
+
+    with_test_env(|| {
+        Command::cargo_bin("stag")?
+            .args(["a", "proj", &norm_repo])
+            .assert()
+            .success();
+        Command::cargo_bin("stag")?
+            .args(["a", "rust", &norm_main])
+            .assert()
+            .success();
+
+        Command::cargo_bin("stag")?
+            .args(["sidecar", "export", &norm_repo])
+            .assert()
+            .success()
+            .stdout(format!("Exported 2 path(s) to {}/.stag.toml\n", norm_repo));
+
+        Ok::<_, anyhow::Error>(())
+    })?;
+
+    // A fresh store, like on another machine after cloning
+    with_test_env(|| {
+        Command::cargo_bin("stag")?
+            .args(["sidecar", "import", &normalize_path(temp_dir.path())?, "-r"])
+            .assert()
+            .success()
+            .stdout("Imported 2 tag association(s), skipped 0 missing path(s)\n");
+
+        Command::cargo_bin("stag")?
+            .args(["i", &norm_main])
+            .assert()
+            .success()
+            .stdout("rust\n");
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_frontmatter() -> Result<()> {
+    with_test_env(|| {
+        let temp_dir = TempDir::new()?;
+        let vault = normalize_path(temp_dir.path())?;
+        let note = temp_dir.path().join("ideas/note.md");
+        std::fs::create_dir(temp_dir.path().join("ideas"))?;
+        std::fs::write(&note, "---\ntitle: Note\ntags: [idea]\n---\n# Note\n")?;
+
+        Command::cargo_bin("stag")?
+            .args(["frontmatter", "pull", &vault, "-r"])
+            .assert()
+            .success()
+            .stdout("Updated 1 file(s)\n");
+
+        Command::cargo_bin("stag")?
+            .args(["s", "idea"])
+            .assert()
+            .success()
+            .stdout(format!("{}\n", normalize_path(&note)?));
+
+        Command::cargo_bin("stag")?
+            .args(["a", "todo", &normalize_path(&note)?])
+            .assert()
+            .success();
+
+        Command::cargo_bin("stag")?
+            .args(["frontmatter", "push", &vault, "-r"])
+            .assert()
+            .success()
+            .stdout("Updated 1 file(s)\n");
+
+        assert_eq!(
+            std::fs::read_to_string(&note)?,
+            "---\ntitle: Note\ntags: [idea, todo]\n---\n# Note\n"
+        );
+
+        // Both sides change, the note is reported and left alone
+        std::fs::write(&note, "---\ntitle: Note\ntags: [idea]\n---\n# Note\n")?;
+        Command::cargo_bin("stag")?
+            .args(["a", "urgent", &normalize_path(&note)?])
+            .assert()
+            .success();
+
+        Command::cargo_bin("stag")?
+            .args(["frontmatter", "push", &vault, "-r"])
+            .assert()
+            .success()
+            .stdout("Updated 0 file(s)\n")
+            .stderr(predicate::str::contains("Conflict: "))
+            .stderr(predicate::str::contains("--force"));
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_autotag_rules() -> Result<()> {
+    with_test_env(|| {
+        let home = TempDir::new()?;
+        let temp_dir = TempDir::new()?;
+        let project = temp_dir.path().join("project");
+        std::fs::create_dir(&project)?;
+        std::fs::write(project.join("Cargo.toml"), "")?;
+        std::fs::write(temp_dir.path().join("notes.txt"), "hi")?;
+
+        std::fs::create_dir_all(home.path().join("stag"))?;
+        std::fs::write(
+            home.path().join("stag/config.toml"),
+            r#"
+[autotag]
+builtin = false
+
+[[autotag.rules]]
+name = "rust projects"
+tags = ["rust", "proj"]
+marker = "Cargo.toml"
+
+[[autotag.rules]]
+tags = ["text"]
+extensions = ["txt"]
+max_size = "1K"
+"#,
+        )?;
+
+        let stag = || -> Result<Command> {
+            let mut cmd = Command::cargo_bin("stag")?;
+            cmd.env("XDG_CONFIG_HOME", home.path());
+            Ok(cmd)
+        };
+
+        stag()?
+            .args(["rules", "test", &normalize_path(&project)?])
+            .assert()
+            .success()
+            .stdout("rust projects: rust, proj\n");
+
+        stag()?
+            .args(["at", &normalize_path(temp_dir.path())?, "-r", "-q"])
+            .assert()
+            .success();
+
+        stag()?
+            .args(["s", "rust", "proj"])
+            .assert()
+            .success()
+            .stdout(format!("{}\n", normalize_path(&project)?));
+
+        // Built-ins are off, so nothing got `file`
+        stag()?
+            .args(["ls", "file"])
+            .assert()
+            .success()
+            .stdout(predicate::str::is_empty());
+
+        stag()?
+            .args(["ls", "text"])
+            .assert()
+            .success()
+            .stdout(format!(
+                "{}\n",
+                normalize_path(&temp_dir.path().join("notes.txt"))?
+            ));
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_autotag_sniffing() -> Result<()> {
+    with_test_env(|| {
+        let temp_dir = TempDir::new()?;
+        let script = temp_dir.path().join("deploy");
+        std::fs::write(&script, "#!/usr/bin/env python3\nprint('hi')\n")?;
+        let photo = temp_dir.path().join("holiday.jpg");
+        std::fs::write(&photo, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR")?;
+
+        Command::cargo_bin("stag")?
+            .args(["at", &normalize_path(temp_dir.path())?, "-r", "-q"])
+            .assert()
+            .success();
+
+        Command::cargo_bin("stag")?
+            .args(["s", "shebang:python", "script"])
+            .assert()
+            .success()
+            .stdout(format!("{}\n", normalize_path(&script)?));
+
+        Command::cargo_bin("stag")?
+            .args(["s", "mime:image/png", "mismatched-extension"])
+            .assert()
+            .success()
+            .stdout(format!("{}\n", normalize_path(&photo)?));
+
+        Command::cargo_bin("stag")?
+            .args(["ls", "jpeg"])
+            .assert()
+            .success()
+            .stdout(predicate::str::is_empty());
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_autotag_projects() -> Result<()> {
+    with_test_env(|| {
+        let temp_dir = TempDir::new()?;
+        let cli = temp_dir.path().join("cli");
+        let web = temp_dir.path().join("web");
+        std::fs::create_dir(&cli)?;
+        std::fs::create_dir(&web)?;
+        std::fs::write(cli.join("Cargo.toml"), "[workspace]\nmembers = []\n")?;
+        std::fs::write(web.join("package.json"), "{}")?;
+
+        Command::cargo_bin("stag")?
+            .args(["at", &normalize_path(&cli)?, &normalize_path(&web)?, "-q"])
+            .assert()
+            .success();
+
+        Command::cargo_bin("stag")?
+            .args(["s", "rust", "workspace:cargo"])
+            .assert()
+            .success()
+            .stdout(format!("{}\n", normalize_path(&cli)?));
+
+        Command::cargo_bin("stag")?
+            .args(["s", "node", "project"])
+            .assert()
+            .success()
+            .stdout(format!("{}\n", normalize_path(&web)?));
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_autotag_dependencies() -> Result<()> {
+    with_test_env(|| {
+        let temp_dir = TempDir::new()?;
+        let cli = temp_dir.path().join("cli");
+        let web = temp_dir.path().join("web");
+        std::fs::create_dir(&cli)?;
+        std::fs::create_dir(&web)?;
+        std::fs::write(
+            cli.join("Cargo.toml"),
+            "[package]\nname = \"cli\"\n\n[dependencies]\nserde = \"1\"\n",
+        )?;
+        std::fs::write(
+            web.join("package.json"),
+            r#"{"dependencies": {"react": "18"}}"#,
+        )?;
+
+        Command::cargo_bin("stag")?
+            .args(["at", &normalize_path(temp_dir.path())?, "-r", "-q"])
+            .assert()
+            .success();
+
+        Command::cargo_bin("stag")?
+            .args(["s", "dep:serde", "--dirs"])
+            .assert()
+            .success()
+            .stdout(format!("{}\n", normalize_path(&cli)?));
+
+        Command::cargo_bin("stag")?
+            .args(["s", "dep:react", "node"])
+            .assert()
+            .success()
+            .stdout(format!("{}\n", normalize_path(&web)?));
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_autotag_git() -> Result<()> {
+    let has_git = std::process::Command::new("git")
+        .arg("--version")
+        .output()
+        .is_ok_and(|output| output.status.success());
+    if !has_git {
+        return Ok(());
+    }
+
+    with_test_env(|| {
+        let temp_dir = TempDir::new()?;
+        let clean = temp_dir.path().join("clean");
+        let dirty = temp_dir.path().join("dirty");
+
+        let git = |repo: &std::path::Path, args: &[&str]| -> Result<()> {
+            let status = std::process::Command::new("git")
+                .args(["-c", "user.name=stag", "-c", "user.email=stag@example.com"])
+                .args(args)
+                .current_dir(repo)
+                .env("GIT_CONFIG_NOSYSTEM", "1")
+                .env("GIT_CONFIG_GLOBAL", "/dev/null")
+                .status()?;
+            assert!(status.success());
+            Ok(())
+        };
+
+        for repo in [&clean, &dirty] {
+            std::fs::create_dir(repo)?;
+            std::fs::write(repo.join("a.txt"), "a")?;
+            git(repo, &["init", "-q", "-b", "main"])?;
+            git(repo, &["add", "."])?;
+            git(repo, &["commit", "-q", "-m", "init"])?;
+        }
+        std::fs::write(dirty.join("a.txt"), "changed")?;
+
+        Command::cargo_bin("stag")?
+            .args(["at", &normalize_path(temp_dir.path())?, "-r", "-q"])
+            .assert()
+            .success();
+
+        Command::cargo_bin("stag")?
+            .args(["s", "git:dirty", "--dirs"])
+            .assert()
+            .success()
+            .stdout(format!("{}\n", normalize_path(&dirty)?));
+
+        Command::cargo_bin("stag")?
+            .args(["s", "git:branch=main", "--dirs"])
+            .assert()
+            .success()
+            .stdout(predicates::str::contains(normalize_path(&clean)?));
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_autotag_images() -> Result<()> {
+    with_test_env(|| {
+        let temp_dir = TempDir::new()?;
+        let png = |width: u32, height: u32| {
+            let mut data = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+            data.extend(width.to_be_bytes());
+            data.extend(height.to_be_bytes());
+            data.extend([8, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            data.extend(b"IEND");
+            data
+        };
+        let phone = temp_dir.path().join("phone.png");
+        std::fs::write(&phone, png(1080, 1920))?;
+        let wallpaper = temp_dir.path().join("wallpaper.png");
+        std::fs::write(&wallpaper, png(3840, 2160))?;
+
+        Command::cargo_bin("stag")?
+            .args(["at", &normalize_path(temp_dir.path())?, "-r", "-q"])
+            .assert()
+            .success();
+
+        Command::cargo_bin("stag")?
+            .args(["s", "orientation:portrait", "resolution:1080p"])
+            .assert()
+            .success()
+            .stdout(format!("{}\n", normalize_path(&phone)?));
+
+        Command::cargo_bin("stag")?
+            .args(["s", "resolution:4k", "orientation:landscape"])
+            .assert()
+            .success()
+            .stdout(format!("{}\n", normalize_path(&wallpaper)?));
+
+        Ok(())
+    })
+}
+
+#[test]
+#[serial]
+fn test_invalid_busy_timeout() -> Result<()> {
+    with_test_env(|| {
+        Command::cargo_bin("stag")?
+            .args(["ls", "rust"])
+            .env("STAG_BUSY_TIMEOUT", "5s")
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains(
+                "Invalid STAG_BUSY_TIMEOUT \"5s\"",
+            ));
+
+        Ok(())
+    })
+}